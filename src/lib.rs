@@ -5,15 +5,129 @@
 extern crate alloc;
 
 #[cfg(not(feature = "std"))]
-use alloc::{boxed::Box, vec::Vec};
-use core::{borrow::Borrow, cmp::Ordering, default::Default, mem};
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct Tree<K, V>(Option<Box<Node<K, V>>>);
+#[cfg(feature = "std")]
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "probe")]
+use core::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    default::Default,
+    iter::FromIterator,
+    mem::{self, MaybeUninit},
+    ops::{AddAssign, Bound, Deref, Index, RangeBounds},
+};
+
+#[derive(Debug, Clone)]
+pub struct Tree<K, V> {
+    root: Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+    probe: Probe,
+}
 
 impl<K, V> Default for Tree<K, V> {
     fn default() -> Self {
-        Self(None)
+        Self::with_strategy(BalanceStrategy::None)
+    }
+}
+
+/// Two trees are equal if they hold equal entries, regardless of shape
+/// or strategy.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::{BalanceStrategy, Tree};
+///
+/// let mut a = Tree::with(1, 'a');
+/// a.insert(2, 'b');
+/// let mut b = Tree::with_strategy(BalanceStrategy::Avl);
+/// b.insert(2, 'b');
+/// b.insert(1, 'a');
+/// assert_eq!(a, b);
+/// ```
+impl<K: PartialEq, V: PartialEq> PartialEq for Tree<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        size(&self.root) == size(&other.root) && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for Tree<K, V> {}
+
+/// How a `Tree` keeps itself balanced as entries are inserted.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum BalanceStrategy {
+    /// Plain binary search tree. Sorted inserts degrade it to a list.
+    #[default]
+    None,
+    /// AVL: sibling subtree heights differ by at most one, so the height
+    /// stays under about 1.44 log2(n) through inserts and removes.
+    Avl,
+    /// Weight balanced: sibling subtree sizes stay within a constant
+    /// factor of each other.
+    WeightBalanced,
+    /// Red-black: the root is black, no red node has a red child, and
+    /// every path down from a node passes the same number of black
+    /// nodes, so the height stays under 2 log2(n + 1).
+    ///
+    /// There is no separate `RbTree<K, V>` type; a red-black tree is
+    /// `Tree::with_strategy(BalanceStrategy::RedBlack)`.
+    RedBlack,
+}
+
+impl BalanceStrategy {
+    /// The strategies in the order of their byte tags.
+    const ALL: [BalanceStrategy; 4] = [
+        BalanceStrategy::None,
+        BalanceStrategy::Avl,
+        BalanceStrategy::WeightBalanced,
+        BalanceStrategy::RedBlack,
+    ];
+
+    /// Returns the tag that [`Tree::to_preorder_bytes`] writes first.
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the strategy for a tag written by [`to_byte`](Self::to_byte).
+    fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(byte)).copied()
+    }
+}
+
+/// Counts key comparisons made by `get` and `insert` once enabled by
+/// [`Tree::set_probe_counter`]. Without the `probe` feature it is
+/// zero-sized and does nothing.
+#[derive(Debug, Default)]
+struct Probe {
+    #[cfg(feature = "probe")]
+    comparisons: Option<AtomicU64>,
+}
+
+impl Probe {
+    /// Records one comparison.
+    #[inline]
+    fn hit(&self) {
+        #[cfg(feature = "probe")]
+        if let Some(count) = &self.comparisons {
+            count.fetch_add(1, Relaxed);
+        }
+    }
+}
+
+impl Clone for Probe {
+    fn clone(&self) -> Self {
+        Self {
+            #[cfg(feature = "probe")]
+            comparisons: self
+                .comparisons
+                .as_ref()
+                .map(|count| AtomicU64::new(count.load(Relaxed))),
+        }
     }
 }
 
@@ -31,6 +145,32 @@ impl<K, V> Tree<K, V> {
         Self::default()
     }
 
+    /// Creates an empty `Tree` that balances itself using `strategy`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{BalanceStrategy, Tree};
+    ///
+    /// let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+    /// for k in 0..1000 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert!(tree.height() < 15);
+    /// ```
+    pub fn with_strategy(strategy: BalanceStrategy) -> Self {
+        Self {
+            root: None,
+            strategy,
+            probe: Probe::default(),
+        }
+    }
+
+    /// Returns the `BalanceStrategy` this `Tree` was created with.
+    pub fn strategy(&self) -> BalanceStrategy {
+        self.strategy
+    }
+
     /// Creates a `Tree` with an initial key-value pair.
     ///
     /// # Examples
@@ -69,33 +209,99 @@ impl<K, V> Tree<K, V> {
     where
         K: Ord,
     {
-        match &mut self.0 {
-            inner @ None => {
-                let _ = mem::replace(inner, Some(Box::new(Node::new(k, v))));
-                None
-            }
-            Some(node) => node.as_mut().insert(k, v),
+        let old = insert(&mut self.root, k, v, self.strategy, &self.probe).old;
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        old
+    }
+
+    /// Inserts a key-value pair like [`insert`](Tree::insert), and
+    /// reports what rebalancing it caused.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{BalanceStrategy, Tree};
+    ///
+    /// let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+    /// tree.insert(1, 'a');
+    /// assert_eq!(tree.insert_reporting(2, 'b').rotations, 0);
+    /// let report = tree.insert_reporting(3, 'c');
+    /// assert_eq!(report.rotations, 1);
+    /// assert_eq!(report.height, 1);
+    /// assert_eq!(tree.insert_reporting(3, 'd').old, Some('c'));
+    /// ```
+    pub fn insert_reporting(&mut self, k: K, v: V) -> InsertReport<V>
+    where
+        K: Ord,
+    {
+        let inserted = insert(&mut self.root, k, v, self.strategy, &self.probe);
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        InsertReport {
+            old: inserted.old,
+            rotations: inserted.rotations,
+            height: self.height(),
         }
     }
 
-    /// Returns a reference to the value for `k`.
+    /// Inserts a key-value pair like [`insert`](Tree::insert), returning
+    /// a mutable reference to the stored value along with the value it
+    /// displaced, if any.
+    ///
+    /// Keys are compared in a single descent. The stored value is then
+    /// found again by rank, without comparing keys.
     ///
     /// # Examples
     ///
     /// ```
     /// use binsearchtree::Tree;
     ///
-    /// let tree = Tree::with("cow", "moo");
-    /// assert_eq!(tree.get("cow"), Some(&"moo"));
+    /// let mut tree = Tree::with("cat", 1);
+    /// let (v, old) = tree.insert_mut("cat", 10);
+    /// *v += 1;
+    /// assert_eq!(old, Some(1));
+    /// assert_eq!(tree.get("cat"), Some(&11));
+    /// ```
+    pub fn insert_mut(&mut self, k: K, v: V) -> (&mut V, Option<V>)
+    where
+        K: Ord,
+    {
+        let inserted = insert(&mut self.root, k, v, self.strategy, &self.probe);
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        let node = select_mut(&mut self.root, inserted.rank).expect("inserted above");
+        (&mut node.v, inserted.old)
+    }
+
+    /// Removes the entry for `k`, returning its value, or `None` if the
+    /// tree has no entry for `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("cat", "meow");
+    /// assert_eq!(tree.remove("cat"), Some("meow"));
+    /// assert_eq!(tree.remove("cat"), None);
     /// ```
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
     where
         K: Ord + Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
-        self.0.as_ref().and_then(|node| node.get(k))
+        let removed = remove_node(&mut self.root, k, self.strategy).map(|node| node.v);
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        removed
     }
-    /// Returns the number of key-value pairs in the Tree.
+
+    /// Returns a mutable reference to the value for `k`, inserting `v`
+    /// first if `k` is absent.
+    ///
+    /// The returned flag is `true` if `k` was already in the tree, in
+    /// which case `v` is dropped and the existing value is left as is.
     ///
     /// # Examples
     ///
@@ -103,19 +309,24 @@ impl<K, V> Tree<K, V> {
     /// use binsearchtree::Tree;
     ///
     /// let mut tree = Tree::new();
-    /// assert_eq!(tree.len(), 0);
-    /// tree.insert(1, 'a');
-    /// tree.insert(2, 'b');
-    /// assert_eq!(tree.len(), 2);
+    /// let (v, existed) = tree.entry_or_insert_reporting("pig", 1);
+    /// assert!(!existed);
+    /// *v += 1;
+    /// assert_eq!(tree.entry_or_insert_reporting("pig", 0), (&mut 2, true));
     /// ```
-    pub fn len(&self) -> usize
+    pub fn entry_or_insert_reporting(&mut self, k: K, v: V) -> (&mut V, bool)
     where
         K: Ord,
     {
-        self.0.as_ref().map_or(0, |node| node.len())
+        let (node, existed) = self.find_or_insert_with(k, || v);
+        (&mut node.v, existed)
     }
 
-    /// Returns `true` if the tree is empty.
+    /// Returns an [`EntryRef`] for `k`, which resolves to both the
+    /// stored key and its value.
+    ///
+    /// This is useful for interning, where the caller wants a reference
+    /// to the tree's canonical copy of a key rather than its own.
     ///
     /// # Examples
     ///
@@ -123,387 +334,7515 @@ impl<K, V> Tree<K, V> {
     /// use binsearchtree::Tree;
     ///
     /// let mut tree = Tree::new();
-    /// assert_eq!(tree.is_empty(), true);
-    /// tree.insert(3, 'c');
-    /// assert_eq!(tree.is_empty(), false);
+    /// let (k, v) = tree.entry_ref(String::from("owl")).or_insert(0);
+    /// *v += 1;
+    /// assert_eq!(k, "owl");
     /// ```
-    pub fn is_empty(&self) -> bool {
-        self.0.is_none()
+    pub fn entry_ref(&mut self, k: K) -> EntryRef<'_, K, V> {
+        EntryRef { tree: self, key: k }
     }
 
-    /// Returns an sorted key-value iterator over the `Tree`.
+    /// Returns the [`Entry`] for `k`, for in-place manipulation.
     ///
     /// # Examples
     ///
     /// ```
-    /// use binsearchtree::Tree;
-    ///
-    /// let mut tree = Tree::with(3, 'a');
-    /// tree.insert(2, 'b');
-    /// tree.insert(1, 'c');
+    /// use binsearchtree::{Entry, Tree};
     ///
-    /// // Collect key-value pairs.
-    /// let key_vals: Vec<(i32, char)> = tree.iter().map(|(&k, &v)| (k, v)).collect();
-    /// assert_eq!(key_vals, vec![(1, 'c'), (2, 'b'), (3, 'a')]);
+    /// let mut tree = Tree::with("cow", 1);
+    /// if let Entry::Occupied(mut e) = tree.entry("cow") {
+    ///     *e.get_mut() += 1;
+    /// }
+    /// if let Entry::Vacant(e) = tree.entry("hen") {
+    ///     e.insert(7);
+    /// }
+    /// assert_eq!(tree.get("cow"), Some(&2));
+    /// assert_eq!(tree.get("hen"), Some(&7));
     /// ```
-    pub fn iter(&self) -> Iter<K, V> {
-        Iter::new(self)
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V>
+    where
+        K: Ord,
+    {
+        let (rank, found) = self.search_rank(&k);
+        if !found {
+            return Entry::Vacant(VacantEntry {
+                tree: self,
+                key: k,
+                rank,
+            });
+        }
+        Entry::Occupied(OccupiedEntry {
+            node: select_mut(&mut self.root, rank).expect("found above"),
+        })
     }
 
-    fn nodes(&self) -> NodeIter<K, V> {
-        NodeIter::new(self)
+    /// Adds one to the value for `k`, starting from `V::default()` if
+    /// `k` is absent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut freqs: Tree<char, u32> = Tree::new();
+    /// for c in "hello".chars() {
+    ///     freqs.increment(c);
+    /// }
+    /// assert_eq!(freqs.get(&'l'), Some(&2));
+    /// assert_eq!(freqs.get(&'h'), Some(&1));
+    /// assert_eq!(freqs.get(&'z'), None);
+    /// ```
+    pub fn increment(&mut self, k: K)
+    where
+        K: Ord,
+        V: Default + AddAssign<V> + From<u8>,
+    {
+        let (node, _) = self.find_or_insert_with(k, V::default);
+        node.v += V::from(1);
     }
-}
 
-/// A node in a binary search tree
-#[derive(Debug, PartialEq, Clone)]
-pub struct Node<K, V> {
-    /// This node's k
-    k: K,
-    /// This node's v
-    v: V,
-    /// L child
-    l: Option<Box<Self>>,
-    /// R child
-    r: Option<Box<Self>>,
-}
+    /// Runs `f` on the value for `k`, first inserting `V::default()` if
+    /// `k` is absent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree: Tree<&str, Vec<u32>> = Tree::new();
+    /// tree.modify_default("odd", |v| v.push(1));
+    /// tree.modify_default("odd", |v| v.push(3));
+    /// assert_eq!(tree.get("odd"), Some(&vec![1, 3]));
+    /// ```
+    pub fn modify_default<F: FnOnce(&mut V)>(&mut self, k: K, f: F)
+    where
+        K: Ord,
+        V: Default,
+    {
+        let (node, _) = self.find_or_insert_with(k, V::default);
+        f(&mut node.v);
+    }
 
-impl<K: Ord, V> Node<K, V> {
-    pub(crate) fn new(k: K, v: V) -> Self {
-        Self {
-            k,
-            v,
-            l: None,
-            r: None,
+    /// Returns a mutable reference to the value for `k` if `is_fresh`
+    /// accepts it, and otherwise stores and returns `f()` in its place.
+    ///
+    /// An absent key is filled with `f()` as well. This suits caches
+    /// whose values carry their own expiry, such as a timestamp.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let now = 100;
+    /// let mut cache = Tree::with("weather", (40, "rain"));
+    /// let fresh = |&(at, _): &(u32, &str)| now - at < 30;
+    /// let forecast = cache.get_fresh_or_insert_with("weather", fresh, || (now, "sun"));
+    /// assert_eq!(*forecast, (100, "sun"));
+    /// ```
+    pub fn get_fresh_or_insert_with<P, F>(&mut self, k: K, is_fresh: P, f: F) -> &mut V
+    where
+        K: Ord,
+        P: Fn(&V) -> bool,
+        F: FnOnce() -> V,
+    {
+        match self.entry(k) {
+            Entry::Occupied(mut e) => {
+                if !is_fresh(e.get()) {
+                    e.insert(f());
+                }
+                e.into_mut()
+            }
+            Entry::Vacant(e) => e.insert(f()),
         }
     }
 
-    pub(crate) fn insert(&mut self, k: K, v: V) -> Option<V> {
-        let lr = match self.k.cmp(&k) {
-            Ordering::Greater => &mut self.l,
-            Ordering::Equal => {
-                return Some(mem::replace(&mut self.v, v));
-            }
-            Ordering::Less => &mut self.r,
-        };
-        match lr {
-            None => {
-                *lr = Some(Box::new(Self::new(k, v)));
-                None
+    /// Runs `apply` on the value for each key in `deltas` with the
+    /// paired delta. Keys the tree doesn't contain are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut balances = Tree::with("ann", 10);
+    /// balances.insert("bob", 5);
+    /// balances.apply_deltas(vec![("bob", -2), ("cat", 7)], |v, d| *v += d);
+    /// assert_eq!(balances.get("bob"), Some(&3));
+    /// assert_eq!(balances.get("cat"), None);
+    /// ```
+    pub fn apply_deltas<D, I, F>(&mut self, deltas: I, mut apply: F)
+    where
+        K: Ord,
+        I: IntoIterator<Item = (K, D)>,
+        F: FnMut(&mut V, D),
+    {
+        for (k, d) in deltas {
+            if let Some(v) = self.get_mut(&k) {
+                apply(v, d);
             }
-            Some(node) => node.as_mut().insert(k, v),
         }
     }
 
-    pub(crate) fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    /// Runs `f` on the value for each key in `keys`, returning how many
+    /// were modified. Keys the tree doesn't contain are skipped.
+    ///
+    /// A key listed twice is modified, and counted, twice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut stock = Tree::with("nails", 100);
+    /// stock.insert("screws", 50);
+    /// assert_eq!(stock.modify_existing(vec!["screws", "bolts"], |v| *v -= 10), 1);
+    /// assert_eq!(stock.get("screws"), Some(&40));
+    /// ```
+    pub fn modify_existing<I, F>(&mut self, keys: I, mut f: F) -> usize
     where
-        K: Borrow<Q>,
-        Q: Ord,
+        K: Ord,
+        I: IntoIterator<Item = K>,
+        F: FnMut(&mut V),
     {
-        let lr = match self.k.borrow().cmp(k) {
-            Ordering::Greater => &self.l,
-            Ordering::Equal => return Some(&self.v),
-            Ordering::Less => &self.r,
-        };
-        match lr {
-            None => None,
-            Some(node) => node.as_ref().get(k),
+        let mut modified = 0;
+        for k in keys {
+            if let Some(v) = self.get_mut(&k) {
+                f(v);
+                modified += 1;
+            }
         }
+        modified
     }
 
-    pub(crate) fn len(&self) -> usize {
-        self.l.as_ref().map_or(0, |node| node.len())
-            + 1
-            + self.r.as_ref().map_or(0, |node| node.len())
+    /// Returns a reference to the value for `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let tree = Tree::with("cow", "moo");
+    /// assert_eq!(tree.get("cow"), Some(&"moo"));
+    /// ```
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            self.probe.hit();
+            curr = match node.k.borrow().cmp(k) {
+                Ordering::Greater => node.l.as_deref(),
+                Ordering::Equal => return Some(&node.v),
+                Ordering::Less => node.r.as_deref(),
+            };
+        }
+        None
     }
-}
-
-pub struct Iter<'a, K, V>(NodeIter<'a, K, V>);
 
-impl<'a, K, V> Iter<'a, K, V> {
-    fn new(tree: &'a Tree<K, V>) -> Self {
-        Iter(NodeIter::new(tree))
+    /// Replaces the value for `k` with `new` only if it currently equals
+    /// `expected`.
+    ///
+    /// Returns `Ok(())` if the value was replaced, `Err(Some(current))`
+    /// with a clone of the current value if it didn't match, or
+    /// `Err(None)` if the tree has no entry for `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("ver", 1);
+    /// assert_eq!(tree.compare_and_swap("ver", &1, 2), Ok(()));
+    /// assert_eq!(tree.compare_and_swap("ver", &1, 3), Err(Some(2)));
+    /// assert_eq!(tree.compare_and_swap("gen", &1, 3), Err(None));
+    /// ```
+    pub fn compare_and_swap<Q>(&mut self, k: &Q, expected: &V, new: V) -> Result<(), Option<V>>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+        V: PartialEq + Clone,
+    {
+        match self.get_mut(k) {
+            Some(v) if *v == *expected => {
+                *v = new;
+                Ok(())
+            }
+            Some(v) => Err(Some(v.clone())),
+            None => Err(None),
+        }
     }
-}
 
-impl<'a, K, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|node| (&node.k, &node.v))
+    /// Returns a [`PooledTree`] handle that inserts and removes through
+    /// `pool`, reusing its node allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{NodePool, Tree};
+    ///
+    /// let mut pool = NodePool::with_capacity(2);
+    /// let mut tree = Tree::new();
+    /// {
+    ///     let mut pooled = tree.with_pool(&mut pool);
+    ///     pooled.insert("a", 1);
+    ///     pooled.insert("b", 2);
+    ///     assert_eq!(pooled.remove("a"), Some(1));
+    /// }
+    /// assert_eq!(tree.len(), 1);
+    /// assert_eq!(pool.len(), 1);
+    /// ```
+    pub fn with_pool<'a>(&'a mut self, pool: &'a mut NodePool<K, V>) -> PooledTree<'a, K, V> {
+        PooledTree { tree: self, pool }
     }
-}
-
-pub struct NodeIter<'a, K, V> {
-    curr: Option<&'a Node<K, V>>,
-    stack: Vec<&'a Node<K, V>>,
-}
 
-impl<'a, K, V> NodeIter<'a, K, V> {
-    pub fn new(tree: &'a Tree<K, V>) -> Self {
-        Self {
-            curr: tree.0.as_deref(),
-            stack: Vec::new(),
-        }
+    /// Returns `true` if the tree has an entry for `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let tree = Tree::with("cow", "moo");
+    /// assert!(tree.contains_key("cow"));
+    /// assert!(!tree.contains_key("pig"));
+    /// ```
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find_node(k).is_some()
     }
-}
-
-impl<'a, K, V> Iterator for NodeIter<'a, K, V> {
-    type Item = &'a Node<K, V>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(curr) = self.curr {
-            self.stack.push(curr);
-            self.curr = curr.l.as_deref();
-        }
-        if let Some(it) = self.stack.pop() {
-            self.curr = it.r.as_deref();
-            Some(it)
+    /// Returns a mutable reference to the value for `k`, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("cow", 1);
+    /// if let Some(v) = tree.get_mut("cow") {
+    ///     *v += 1;
+    /// }
+    /// assert_eq!(tree.get("cow"), Some(&2));
+    /// ```
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.root.as_mut().and_then(|node| node.get_mut(k))
+    }
+
+    /// Replaces the value for `k` with `V::default()` and returns the
+    /// old value, or `None` if `k` is absent.
+    ///
+    /// Unlike [`remove`](Tree::remove), the entry stays in the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("hits", 7);
+    /// assert_eq!(tree.take_value("hits"), Some(7));
+    /// assert_eq!(tree.get("hits"), Some(&0));
+    /// assert_eq!(tree.take_value("misses"), None);
+    /// ```
+    pub fn take_value<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+        V: Default,
+    {
+        self.get_mut(k).map(mem::take)
+    }
+
+    /// Starts or stops counting the key comparisons made by
+    /// [`get`](Tree::get) and [`insert`](Tree::insert), resetting the
+    /// count to zero either way.
+    ///
+    /// The count is a relaxed atomic, so a `Tree` stays `Sync` with the
+    /// `probe` feature enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// tree.set_probe_counter(true);
+    /// for k in 0..8 {
+    ///     tree.insert(k, ());
+    /// }
+    /// tree.get(&7);
+    /// // Sorted inserts with no balancing build a list: 0 + 1 + ... + 7
+    /// // comparisons to insert, then 8 to find the last key.
+    /// assert_eq!(tree.comparison_count(), 36);
+    /// ```
+    #[cfg(feature = "probe")]
+    pub fn set_probe_counter(&mut self, enabled: bool) {
+        self.probe.comparisons = if enabled {
+            Some(AtomicU64::new(0))
         } else {
             None
+        };
+    }
+
+    /// Returns the number of comparisons counted since
+    /// [`set_probe_counter`](Tree::set_probe_counter) enabled counting,
+    /// or zero if counting is off.
+    #[cfg(feature = "probe")]
+    pub fn comparison_count(&self) -> u64 {
+        self.probe
+            .comparisons
+            .as_ref()
+            .map_or(0, |count| count.load(Relaxed))
+    }
+
+    /// Looks up each of `sorted_keys`, returning the results in query
+    /// order.
+    ///
+    /// Instead of descending from the root for every key, this does a
+    /// single in-order walk merged against the queries, taking O(n + m).
+    /// `sorted_keys` must be in ascending order; results for an unsorted
+    /// query list are unspecified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// tree.insert(3, 'c');
+    /// assert_eq!(tree.get_sorted(&[&1, &2, &3]), vec![Some(&'a'), None, Some(&'c')]);
+    /// ```
+    pub fn get_sorted<'a, Q>(&'a self, sorted_keys: &[&Q]) -> Vec<Option<&'a V>>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        debug_assert!(sorted_keys.windows(2).all(|pair| pair[0] <= pair[1]));
+        let mut entries = self.iter().peekable();
+        sorted_keys
+            .iter()
+            .map(|&q| {
+                while let Some((k, _)) = entries.peek() {
+                    if (*k).borrow() < q {
+                        entries.next();
+                    } else {
+                        break;
+                    }
+                }
+                match entries.peek() {
+                    Some((k, v)) if (*k).borrow() == q => Some(*v),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the key of the deepest node that has both `a` and `b` in
+    /// its subtree, or `None` if either key is absent.
+    ///
+    /// A node counts as being in its own subtree, so if `a` descends
+    /// from `b` the result is `b`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[4, 2, 6, 1, 3] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.lowest_common_ancestor(&1, &3), Some(&2));
+    /// assert_eq!(tree.lowest_common_ancestor(&3, &6), Some(&4));
+    /// assert_eq!(tree.lowest_common_ancestor(&1, &5), None);
+    /// ```
+    pub fn lowest_common_ancestor<Q>(&self, a: &Q, b: &Q) -> Option<&K>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(a)?;
+        self.get(b)?;
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            let k = node.k.borrow();
+            if hi < k {
+                curr = node.l.as_deref();
+            } else if lo > k {
+                curr = node.r.as_deref();
+            } else {
+                return Some(&node.k);
+            }
+        }
+        None
+    }
+
+    /// Returns the keys on the tree path from `a` to `b`, or `None` if
+    /// either key is absent.
+    ///
+    /// The path climbs from `a` up to the lowest common ancestor and
+    /// then descends to `b`, including both endpoints. Its length minus
+    /// one is the number of edges between the two nodes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[4, 2, 6, 1, 3, 5, 7] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.path_between(&1, &5), Some(vec![&1, &2, &4, &6, &5]));
+    /// ```
+    pub fn path_between<Q>(&self, a: &Q, b: &Q) -> Option<Vec<&K>>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let to_a = self.path_to(a)?;
+        let to_b = self.path_to(b)?;
+        let shared = to_a
+            .iter()
+            .zip(to_b.iter())
+            .take_while(|(x, y)| core::ptr::eq(**x, **y))
+            .count();
+        let up = to_a[shared - 1..].iter().rev();
+        let down = to_b[shared..].iter();
+        Some(up.chain(down).copied().collect())
+    }
+
+    /// Returns mutable references to the values for each of `keys`,
+    /// in the order the keys were given.
+    ///
+    /// Keys not already in the tree are inserted with `V::default()`.
+    /// The references are collected in one in-order pass, so this is
+    /// O(n) in the size of the tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keys` contains duplicates, since that would hand out
+    /// aliasing mutable references.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("a", 1);
+    /// for v in tree.entries_mut(&["b", "a"]) {
+    ///     *v += 10;
+    /// }
+    /// assert_eq!(tree.get("a"), Some(&11));
+    /// assert_eq!(tree.get("b"), Some(&10));
+    /// ```
+    pub fn entries_mut<'a>(&'a mut self, keys: &[K]) -> Vec<&'a mut V>
+    where
+        K: Ord + Clone,
+        V: Default,
+    {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&i, &j| keys[i].cmp(&keys[j]));
+        if order.windows(2).any(|pair| keys[pair[0]] == keys[pair[1]]) {
+            panic!("duplicate key passed to `entries_mut`");
+        }
+        for k in keys {
+            if self.get(k).is_none() {
+                self.insert(k.clone(), V::default());
+            }
+        }
+        // One sorted pass hands out each wanted value exactly once, and
+        // the slots put them back in the order the keys were given.
+        let mut slots: Vec<Option<&'a mut V>> = keys.iter().map(|_| None).collect();
+        let mut wanted = order.into_iter().peekable();
+        for (k, v) in self.iter_mut() {
+            match wanted.peek() {
+                Some(&i) if keys[i] == *k => {
+                    slots[i] = Some(v);
+                    wanted.next();
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        slots
+            .into_iter()
+            .map(|v| v.expect("inserted above"))
+            .collect()
+    }
+
+    /// Returns mutable references to the values for two distinct keys,
+    /// in the order given.
+    ///
+    /// Returns `None` if `a` and `b` are the same key or either is
+    /// absent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut balances = Tree::with("ann", 10);
+    /// balances.insert("bob", 5);
+    /// if let Some((from, to)) = balances.get_pair_mut("ann", "bob") {
+    ///     *from -= 3;
+    ///     *to += 3;
+    /// }
+    /// assert_eq!(balances.get("bob"), Some(&8));
+    /// assert!(balances.get_pair_mut("ann", "ann").is_none());
+    /// ```
+    pub fn get_pair_mut<Q>(&mut self, a: &Q, b: &Q) -> Option<(&mut V, &mut V)>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        // Descend while both keys lie on the same side, then split the
+        // node where their paths part into disjoint borrows.
+        let mut node = self.root.as_deref_mut()?;
+        loop {
+            let side = node.k.borrow().cmp(a);
+            if side == Ordering::Equal || side != node.k.borrow().cmp(b) {
+                break;
+            }
+            node = match side {
+                Ordering::Greater => node.l.as_deref_mut()?,
+                _ => node.r.as_deref_mut()?,
+            };
+        }
+        let sides = (node.k.borrow().cmp(a), node.k.borrow().cmp(b));
+        let Node { v, l, r, .. } = node;
+        let (l, r) = (l.as_deref_mut(), r.as_deref_mut());
+        match sides {
+            (Ordering::Equal, Ordering::Greater) => Some((v, l?.get_mut(b)?)),
+            (Ordering::Equal, Ordering::Less) => Some((v, r?.get_mut(b)?)),
+            (Ordering::Greater, Ordering::Equal) => Some((l?.get_mut(a)?, v)),
+            (Ordering::Less, Ordering::Equal) => Some((r?.get_mut(a)?, v)),
+            (Ordering::Greater, Ordering::Less) => Some((l?.get_mut(a)?, r?.get_mut(b)?)),
+            (Ordering::Less, Ordering::Greater) => Some((r?.get_mut(a)?, l?.get_mut(b)?)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of key-value pairs in the Tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// assert_eq!(tree.len(), 0);
+    /// tree.insert(1, 'a');
+    /// tree.insert(2, 'b');
+    /// assert_eq!(tree.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize
+    where
+        K: Ord,
+    {
+        self.root.as_ref().map_or(0, |node| node.size)
+    }
+
+    /// Returns `true` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// assert_eq!(tree.is_empty(), true);
+    /// tree.insert(3, 'c');
+    /// assert_eq!(tree.is_empty(), false);
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Removes every entry, keeping the `Tree`'s strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// tree.clear();
+    /// assert!(tree.is_empty());
+    /// ```
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    /// Returns an sorted key-value iterator over the `Tree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(3, 'a');
+    /// tree.insert(2, 'b');
+    /// tree.insert(1, 'c');
+    ///
+    /// // Collect key-value pairs.
+    /// let key_vals: Vec<(i32, char)> = tree.iter().map(|(&k, &v)| (k, v)).collect();
+    /// assert_eq!(key_vals, vec![(1, 'c'), (2, 'b'), (3, 'a')]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self)
+    }
+
+    /// Returns a sorted iterator over the `Tree`'s keys and mutable
+    /// references to its values.
+    ///
+    /// Keys stay immutable, since changing one could break the tree's
+    /// ordering.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 10);
+    /// tree.insert(2, 20);
+    /// for (&k, v) in tree.iter_mut() {
+    ///     *v += k;
+    /// }
+    /// assert_eq!(tree.get(&2), Some(&22));
+    /// ```
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut(NodeIterMut::new(self))
+    }
+
+    /// Returns an iterator over the `Tree`'s keys in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(3, 'a');
+    /// tree.insert(1, 'b');
+    /// let keys: Vec<i32> = tree.keys().copied().collect();
+    /// assert_eq!(keys, vec![1, 3]);
+    /// ```
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys(NodeIter::new(self))
+    }
+
+    /// Returns an iterator over the `Tree`'s values, ordered by their
+    /// keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(3, 'a');
+    /// tree.insert(1, 'b');
+    /// let values: String = tree.values().collect();
+    /// assert_eq!(values, "ba");
+    /// ```
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values(NodeIter::new(self))
+    }
+
+    /// Returns an iterator over mutable references to the `Tree`'s
+    /// values, ordered by their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("a", 1);
+    /// tree.insert("b", 2);
+    /// for v in tree.values_mut() {
+    ///     *v *= 10;
+    /// }
+    /// assert_eq!(tree.get("b"), Some(&20));
+    /// ```
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut(NodeIterMut::new(self))
+    }
+
+    /// Returns the entry with the smallest key, walking the left spine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// assert_eq!(tree.first_key_value(), Some((&1, &'a')));
+    /// ```
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(l) = node.l.as_deref() {
+            node = l;
+        }
+        Some((&node.k, &node.v))
+    }
+
+    /// Returns the entry with the largest key, walking the right spine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// assert_eq!(tree.last_key_value(), Some((&2, &'b')));
+    /// ```
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_deref()?;
+        while let Some(r) = node.r.as_deref() {
+            node = r;
+        }
+        Some((&node.k, &node.v))
+    }
+
+    /// Removes and returns the entry with the smallest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// assert_eq!(tree.pop_first(), Some((1, 'a')));
+    /// assert_eq!(tree.pop_first(), Some((2, 'b')));
+    /// assert_eq!(tree.pop_first(), None);
+    /// ```
+    pub fn pop_first(&mut self) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        self.root.as_ref()?;
+        let node = take_min(&mut self.root, self.strategy);
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        Some((node.k, node.v))
+    }
+
+    /// Removes and returns the entry with the largest key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// assert_eq!(tree.pop_last(), Some((2, 'b')));
+    /// assert_eq!(tree.pop_last(), Some((1, 'a')));
+    /// assert_eq!(tree.pop_last(), None);
+    /// ```
+    pub fn pop_last(&mut self) -> Option<(K, V)>
+    where
+        K: Ord,
+    {
+        self.root.as_ref()?;
+        let node = take_max(&mut self.root, self.strategy);
+        blacken_root(&mut self.root, self.strategy);
+        self.debug_assert_valid();
+        Some((node.k, node.v))
+    }
+
+    /// Returns the entry with the smallest value, the one with the
+    /// smallest key among ties.
+    ///
+    /// The tree is ordered by key, so every value is compared in one
+    /// O(n) pass, and the first minimum met in key order is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("a", 3);
+    /// tree.insert("b", 1);
+    /// assert_eq!(tree.min_by_value(), Some((&"b", &1)));
+    /// ```
+    pub fn min_by_value(&self) -> Option<(&K, &V)>
+    where
+        V: Ord,
+    {
+        self.min_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Returns the entry with the largest value, the one with the
+    /// smallest key among ties.
+    ///
+    /// Like [`min_by_value`](Tree::min_by_value), this compares every
+    /// value in one pass, keeping the first maximum met in key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("a", 3);
+    /// tree.insert("b", 1);
+    /// assert_eq!(tree.max_by_value(), Some((&"a", &3)));
+    /// ```
+    pub fn max_by_value(&self) -> Option<(&K, &V)>
+    where
+        V: Ord,
+    {
+        self.max_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Returns the smallest entry under `compare`, the first in key
+    /// order among ties.
+    ///
+    /// Entries are visited in key order and `compare` is called once for
+    /// each entry after the first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, -5i32);
+    /// tree.insert(2, 3);
+    /// let closest = tree.min_by(|a, b| a.1.abs().cmp(&b.1.abs()));
+    /// assert_eq!(closest, Some((&2, &3)));
+    /// ```
+    pub fn min_by<F>(&self, mut compare: F) -> Option<(&K, &V)>
+    where
+        F: FnMut(&(&K, &V), &(&K, &V)) -> Ordering,
+    {
+        self.iter().min_by(|a, b| compare(a, b))
+    }
+
+    /// Returns the largest entry under `compare`, the first in key
+    /// order among ties.
+    ///
+    /// As with [`min_by`](Tree::min_by), `compare` is called once for
+    /// each entry after the first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, -5i32);
+    /// tree.insert(2, 3);
+    /// let farthest = tree.max_by(|a, b| a.1.abs().cmp(&b.1.abs()));
+    /// assert_eq!(farthest, Some((&1, &-5)));
+    /// ```
+    pub fn max_by<F>(&self, mut compare: F) -> Option<(&K, &V)>
+    where
+        F: FnMut(&(&K, &V), &(&K, &V)) -> Ordering,
+    {
+        // `Iterator::max_by` keeps the last of equal elements, so take
+        // the minimum under the reversed order instead.
+        self.iter().min_by(|a, b| compare(b, a))
+    }
+
+    /// Returns a read-only handle to the root node, for walking the
+    /// `Tree`'s structure, or `None` if the tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// let root = tree.root().unwrap();
+    /// assert_eq!(root.key(), &2);
+    /// assert_eq!(root.left().map(|l| l.value()), Some(&'a'));
+    /// assert!(root.right().is_none());
+    /// ```
+    pub fn root(&self) -> Option<NodeRef<'_, K, V>> {
+        self.root.as_deref().map(NodeRef)
+    }
+
+    /// Returns a [`MorrisIter`] that walks the `Tree` in sorted order
+    /// without allocating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[2, 1, 3] {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// let mut stream = tree.stream_in_order();
+    /// let mut sum = 0;
+    /// while let Some((&k, &v)) = stream.next() {
+    ///     sum += k + v;
+    /// }
+    /// assert_eq!(sum, 66);
+    /// ```
+    pub fn stream_in_order(&mut self) -> MorrisIter<'_, K, V> {
+        MorrisIter {
+            curr: self.root.take(),
+            parents: None,
+            state: MorrisState::Descend,
+            tree: self,
+        }
+    }
+
+    /// Returns up to `n` entries whose keys are nearest to `k` in sorted
+    /// order, closest first.
+    ///
+    /// Distance is measured in positions, not key values: an entry
+    /// for `k` itself comes first, followed by alternating steps
+    /// outward from the floor (the largest key below `k`) and the
+    /// ceiling (the smallest key above it). Ties go to the smaller
+    /// key, and once one side runs out the rest come from the other.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in &[10, 20, 30, 40] {
+    ///     tree.insert(*k, ());
+    /// }
+    /// let keys: Vec<i32> = tree.closest_n(&25, 3).into_iter().map(|(&k, _)| k).collect();
+    /// assert_eq!(keys, vec![20, 30, 10]);
+    /// ```
+    pub fn closest_n<Q>(&self, k: &Q, n: usize) -> Vec<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let len = size(&self.root);
+        let (below, found) = self.search_rank(k);
+        let (mut lo, mut hi) = (below, below);
+        let entry = |rank| {
+            let node = self.select_node(rank).expect("rank is in bounds");
+            (&node.k, &node.v)
+        };
+        let mut nearest = Vec::with_capacity(n.min(len));
+        if n > 0 && found {
+            nearest.push(entry(below));
+            hi += 1;
+        }
+        let mut take_lo = true;
+        while nearest.len() < n && (lo > 0 || hi < len) {
+            if (take_lo && lo > 0) || hi == len {
+                lo -= 1;
+                nearest.push(entry(lo));
+            } else {
+                nearest.push(entry(hi));
+                hi += 1;
+            }
+            take_lo = !take_lo;
+        }
+        nearest
+    }
+
+    /// Returns a sorted iterator over the entries whose keys fall within
+    /// `range`.
+    ///
+    /// Keys below the range are skipped while seeding the iterator, so
+    /// the cost is O(height) plus the number of entries yielded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// let keys: Vec<&i32> = tree.range(3..6).map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![&3, &4, &5]);
+    /// assert_eq!(tree.range(8..).count(), 2);
+    /// ```
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let last = match (
+            self.lower_bound_node(range.start_bound()),
+            self.upper_bound_node(range.end_bound()),
+        ) {
+            (Some(first), Some(last)) if first.k.borrow() <= last.k.borrow() => Some(last),
+            _ => None,
+        };
+        Range {
+            iter: NodeIter::from_bound(self, range.start_bound()),
+            last,
+        }
+    }
+
+    /// Returns a sorted iterator over the entries with keys less than
+    /// `end`.
+    ///
+    /// This is [`range`](Tree::range) with only an exclusive upper
+    /// bound, so the traversal stops at the bound rather than filtering
+    /// the rest of the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, ());
+    /// }
+    /// let keys: Vec<&i32> = tree.iter_until(&3).map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![&0, &1, &2]);
+    /// ```
+    pub fn iter_until<Q>(&self, end: &Q) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.range((Bound::Unbounded, Bound::Excluded(end)))
+    }
+
+    /// Returns a sorted iterator over the entries whose keys fall within
+    /// `range` and whose values satisfy `pred`.
+    ///
+    /// Only the key range is visited, as with [`range`](Tree::range).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k % 3);
+    /// }
+    /// let keys: Vec<&i32> = tree.range_filter(2..8, |&v| v == 0).map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![&3, &6]);
+    /// ```
+    pub fn range_filter<Q, R, F>(&self, range: R, mut pred: F) -> impl Iterator<Item = (&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+        F: FnMut(&V) -> bool,
+    {
+        self.range(range).filter(move |(_, v)| pred(v))
+    }
+
+    /// Folds `f` over the entries whose keys fall within `range`, in
+    /// ascending key order, starting from `init`.
+    ///
+    /// Only the key range is visited, as with [`range`](Tree::range).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// assert_eq!(tree.fold_range(2..5, 0, |sum, _, v| sum + v), 90);
+    /// ```
+    pub fn fold_range<Q, R, B, F>(&self, range: R, init: B, mut f: F) -> B
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+        F: FnMut(B, &K, &V) -> B,
+    {
+        self.range(range).fold(init, |acc, (k, v)| f(acc, k, v))
+    }
+
+    /// Returns the first and last entries whose keys fall within
+    /// `range`.
+    ///
+    /// Each endpoint is found with a single descent from the root, so
+    /// this is O(height) regardless of how many entries the range
+    /// spans. Returns `(None, None)` if the range is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// let (first, last) = tree.range_bounds_entries(3..7);
+    /// assert_eq!(first, Some((&3, &30)));
+    /// assert_eq!(last, Some((&6, &60)));
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn range_bounds_entries<Q, R>(&self, range: R) -> (Option<(&K, &V)>, Option<(&K, &V)>)
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let first = self.lower_bound_node(range.start_bound());
+        let last = self.upper_bound_node(range.end_bound());
+        match (first, last) {
+            (Some(first), Some(last)) if first.k <= last.k => {
+                (Some((&first.k, &first.v)), Some((&last.k, &last.v)))
+            }
+            _ => (None, None),
+        }
+    }
+
+    /// Returns the number of keys less than `k`, in O(height).
+    ///
+    /// `k` itself need not be in the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.rank(&20), 1);
+    /// assert_eq!(tree.rank(&25), 2);
+    /// assert_eq!(tree.rank(&5), 0);
+    /// ```
+    pub fn rank<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.search_rank(k).0
+    }
+
+    /// Returns the entry with the `i`th smallest key, counting from 0,
+    /// in O(height).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.select(0), Some((&10, &())));
+    /// assert_eq!(tree.select(2), Some((&30, &())));
+    /// assert_eq!(tree.select(3), None);
+    /// ```
+    pub fn select(&self, i: usize) -> Option<(&K, &V)> {
+        self.select_node(i).map(|node| (&node.k, &node.v))
+    }
+
+    /// Returns the entry with the largest key strictly less than `k`.
+    ///
+    /// `k` itself need not be in the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.predecessor(&20), Some((&10, &())));
+    /// assert_eq!(tree.predecessor(&25), Some((&20, &())));
+    /// assert_eq!(tree.predecessor(&10), None);
+    /// ```
+    pub fn predecessor<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.upper_bound_node(Bound::Excluded(k))
+            .map(|node| (&node.k, &node.v))
+    }
+
+    /// Returns the entry with the smallest key strictly greater than
+    /// `k`.
+    ///
+    /// `k` itself need not be in the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.successor(&20), Some((&30, &())));
+    /// assert_eq!(tree.successor(&5), Some((&10, &())));
+    /// assert_eq!(tree.successor(&30), None);
+    /// ```
+    pub fn successor<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lower_bound_node(Bound::Excluded(k))
+            .map(|node| (&node.k, &node.v))
+    }
+
+    /// Returns the entry with the largest key less than or equal to `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.floor(&20), Some((&20, &())));
+    /// assert_eq!(tree.floor(&25), Some((&20, &())));
+    /// assert_eq!(tree.floor(&5), None);
+    /// ```
+    pub fn floor<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.upper_bound_node(Bound::Included(k))
+            .map(|node| (&node.k, &node.v))
+    }
+
+    /// Returns the entry with the smallest key greater than or equal to
+    /// `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[10, 20, 30] {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.ceiling(&20), Some((&20, &())));
+    /// assert_eq!(tree.ceiling(&25), Some((&30, &())));
+    /// assert_eq!(tree.ceiling(&35), None);
+    /// ```
+    pub fn ceiling<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.lower_bound_node(Bound::Included(k))
+            .map(|node| (&node.k, &node.v))
+    }
+
+    /// Returns a new balanced `Tree` holding clones of the entries whose
+    /// keys fall within `range`, with the same strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// let window = tree.clone_range(3..6);
+    /// let keys: Vec<&i32> = window.keys().collect();
+    /// assert_eq!(keys, vec![&3, &4, &5]);
+    /// assert_eq!(tree.len(), 10);
+    /// ```
+    pub fn clone_range<Q, R>(&self, range: R) -> Tree<K, V>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let entries = NodeIter::from_bound(self, range.start_bound())
+            .take_while(|node| match range.end_bound() {
+                Bound::Included(b) => node.k.borrow() <= b,
+                Bound::Excluded(b) => node.k.borrow() < b,
+                Bound::Unbounded => true,
+            })
+            .map(|node| (node.k.clone(), node.v.clone()))
+            .collect();
+        let mut tree = Tree::with_strategy(self.strategy);
+        tree.rebuild_from(entries);
+        tree
+    }
+
+    /// Returns a sorted iterator over the subtree rooted at `k`, or
+    /// `None` if `k` is absent.
+    ///
+    /// The iterator yields `k` itself along with all of its
+    /// descendants.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for &k in &[4, 2, 6, 1, 3] {
+    ///     tree.insert(k, ());
+    /// }
+    /// let keys: Vec<&i32> = tree.subtree_iter(&2).unwrap().map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![&1, &2, &3]);
+    /// ```
+    pub fn subtree_iter<Q>(&self, k: &Q) -> Option<Iter<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find_node(k).map(|node| Iter(NodeIter::rooted(node)))
+    }
+
+    /// Returns a sorted iterator over owned clones of the `Tree`'s
+    /// key-value pairs, leaving the tree intact.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(String::from("b"), 2);
+    /// tree.insert(String::from("a"), 1);
+    ///
+    /// let pairs: Vec<(String, i32)> = tree.iter_cloned().collect();
+    /// assert_eq!(pairs, vec![(String::from("a"), 1), (String::from("b"), 2)]);
+    /// assert_eq!(tree.len(), 2);
+    /// ```
+    pub fn iter_cloned(&self) -> impl Iterator<Item = (K, V)> + '_
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.iter().map(|(k, v)| (k.clone(), v.clone()))
+    }
+
+    /// Returns the number of distinct values stored in the `Tree`.
+    ///
+    /// References to the values are gathered and sorted to find the
+    /// duplicates, which takes O(n log n) time and a buffer of `len()`
+    /// references.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// tree.insert(2, 'b');
+    /// tree.insert(3, 'a');
+    /// assert_eq!(tree.distinct_value_count(), 2);
+    /// ```
+    pub fn distinct_value_count(&self) -> usize
+    where
+        V: Ord,
+    {
+        let mut values: Vec<&V> = self.iter().map(|(_, v)| v).collect();
+        values.sort();
+        values.dedup();
+        values.len()
+    }
+
+    /// Returns a `BTreeMap` holding clones of the `Tree`'s entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let tree = Tree::with(1, 'a');
+    /// let map = tree.to_btreemap();
+    /// assert_eq!(map.get(&1), Some(&'a'));
+    /// assert_eq!(tree.len(), 1);
+    /// ```
+    pub fn to_btreemap(&self) -> BTreeMap<K, V>
+    where
+        K: Ord + Clone,
+        V: Clone,
+    {
+        self.iter_cloned().collect()
+    }
+
+    /// Retains only the `n` entries with the largest values, then
+    /// rebuilds the `Tree` balanced.
+    ///
+    /// Entries are ranked by descending value. Ties at the cutoff are
+    /// resolved in key order, so the entry with the smaller key is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("a", 3);
+    /// tree.insert("b", 1);
+    /// tree.insert("c", 2);
+    /// tree.retain_top_n_by_value(2);
+    /// assert_eq!(tree.get("a"), Some(&3));
+    /// assert_eq!(tree.get("b"), None);
+    /// assert_eq!(tree.get("c"), Some(&2));
+    /// ```
+    pub fn retain_top_n_by_value(&mut self, n: usize)
+    where
+        V: Ord,
+    {
+        let entries = self.take_sorted();
+        let mut ranked: Vec<usize> = (0..entries.len()).collect();
+        ranked.sort_by(|&a, &b| entries[b].1.cmp(&entries[a].1));
+        let mut keep = vec![false; entries.len()];
+        for &i in ranked.iter().take(n) {
+            keep[i] = true;
+        }
+        let kept = entries
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(entry, keep)| if keep { Some(entry) } else { None })
+            .collect();
+        self.rebuild_from(kept);
+    }
+
+    /// Returns every entry sorted by descending value, with ties in
+    /// ascending key order.
+    ///
+    /// This collects and sorts the entries, so it takes O(n log n).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut scores = Tree::with("ann", 7);
+    /// scores.insert("bob", 9);
+    /// scores.insert("cat", 7);
+    /// assert_eq!(
+    ///     scores.entries_by_value(),
+    ///     vec![(&"bob", &9), (&"ann", &7), (&"cat", &7)]
+    /// );
+    /// ```
+    pub fn entries_by_value(&self) -> Vec<(&K, &V)>
+    where
+        V: Ord,
+    {
+        let mut entries: Vec<(&K, &V)> = self.iter().collect();
+        // The sort is stable, so entries with equal values stay in key order.
+        entries.sort_by(|a, b| b.1.cmp(a.1));
+        entries
+    }
+
+    /// Returns a sorted iterator over every entry along with its depth,
+    /// where the root is at depth 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// tree.insert(3, 'c');
+    /// let depths: Vec<usize> = tree.iter_with_depth().map(|(_, _, d)| d).collect();
+    /// assert_eq!(depths, vec![1, 0, 1]);
+    /// ```
+    pub fn iter_with_depth(&self) -> impl Iterator<Item = (&K, &V, usize)> + '_ {
+        self.iter_to_depth(usize::MAX)
+    }
+
+    /// Returns a sorted iterator over the entries no deeper than
+    /// `max_depth`, along with each entry's depth.
+    ///
+    /// The root is at depth 0. Subtrees below `max_depth` are pruned
+    /// rather than visited, which makes this a cheap way to preview the
+    /// top of a large tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// tree.insert(3, 'c');
+    /// let top: Vec<(&i32, &char, usize)> = tree.iter_to_depth(0).collect();
+    /// assert_eq!(top, vec![(&2, &'b', 0)]);
+    /// ```
+    pub fn iter_to_depth(&self, max_depth: usize) -> impl Iterator<Item = (&K, &V, usize)> + '_ {
+        DepthIter::new(self, max_depth).map(|(node, depth)| (&node.k, &node.v, depth))
+    }
+
+    /// Returns the number of edges on the longest path from the root
+    /// to a leaf.
+    ///
+    /// Both an empty tree and a single-node tree have height 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, ());
+    /// assert_eq!(tree.height(), 0);
+    /// tree.insert(2, ());
+    /// tree.insert(3, ());
+    /// assert_eq!(tree.height(), 2);
+    /// ```
+    pub fn height(&self) -> usize {
+        self.root.as_ref().map_or(0, |node| node.height - 1)
+    }
+
+    /// Returns the number of edges from the root to `k`, or `None` if
+    /// `k` is absent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, ());
+    /// tree.insert(2, ());
+    /// tree.insert(3, ());
+    /// assert_eq!(tree.depth_of(&1), Some(0));
+    /// assert_eq!(tree.depth_of(&3), Some(2));
+    /// assert_eq!(tree.depth_of(&4), None);
+    /// ```
+    pub fn depth_of<Q>(&self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut depth = 0;
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            curr = match node.k.borrow().cmp(k) {
+                Ordering::Greater => node.l.as_deref(),
+                Ordering::Equal => return Some(depth),
+                Ordering::Less => node.r.as_deref(),
+            };
+            depth += 1;
+        }
+        None
+    }
+
+    /// Returns how far the `Tree` is from balanced: its
+    /// [`height`](Tree::height) divided by `ceil(log2(len() + 1))`, the
+    /// fewest levels that could hold `len()` entries.
+    ///
+    /// Values near 1.0 mean balanced, and a tree degenerated into a
+    /// list scores about `len() / log2(len())`. Since height counts
+    /// edges, a perfect tree of `2^k - 1` entries scores `(k - 1) / k`,
+    /// and empty and single-node trees score 0.0. Node heights are
+    /// cached, so this is O(1) and never recurses.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..7 {
+    ///     tree.insert(k, ());
+    /// }
+    /// // Six edges deep where three levels would do.
+    /// assert_eq!(tree.degeneracy_ratio(), 6.0 / 3.0);
+    /// ```
+    pub fn degeneracy_ratio(&self) -> f64 {
+        let len = size(&self.root);
+        if len == 0 {
+            return 0.0;
+        }
+        let min_levels = usize::BITS - len.leading_zeros();
+        self.height() as f64 / f64::from(min_levels)
+    }
+
+    /// Returns the `Tree`'s [`height`](Tree::height) divided by
+    /// `ceil(log2(len()))`, for use as a rebalance trigger.
+    ///
+    /// Values near 1.0 mean balanced and larger values mean skewed;
+    /// trees of size 0 or 1 score 1.0. Unlike
+    /// [`degeneracy_ratio`](Tree::degeneracy_ratio) this divides by
+    /// `ceil(log2(len()))`, so a complete tree of `2^k` entries scores
+    /// exactly 1.0 and a perfect tree of `2^k - 1` entries slightly
+    /// less. This is O(1).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..8 {
+    ///     tree.insert(k, ());
+    /// }
+    /// // Seven edges deep where three would do.
+    /// assert_eq!(tree.balance_factor(), 7.0 / 3.0);
+    /// ```
+    pub fn balance_factor(&self) -> f64 {
+        let len = size(&self.root);
+        if len <= 1 {
+            return 1.0;
+        }
+        let min_height = (len - 1).ilog2() + 1;
+        (height(&self.root) - 1) as f64 / f64::from(min_height)
+    }
+
+    /// Returns `true` if every node's cached height, size and black
+    /// height match the values recomputed from its children.
+    ///
+    /// Rotations are where these fields usually go stale. This checks
+    /// them alone, ignoring key order and the strategy's balance
+    /// invariant; [`validate`](Tree::validate) checks all three.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{BalanceStrategy, Tree};
+    ///
+    /// let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+    /// for k in 0..100 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert!(tree.verify_augmentation());
+    /// ```
+    pub fn verify_augmentation(&self) -> bool {
+        valid_shape(&self.root, BalanceStrategy::None).is_some()
+    }
+
+    /// Returns `true` if the `Tree` is internally consistent: keys are in
+    /// strictly increasing order, every node's cached fields are correct
+    /// as by [`verify_augmentation`](Tree::verify_augmentation), and the
+    /// strategy's balance invariant holds.
+    ///
+    /// Order is judged by `K`'s `Ord`, the same comparison every other
+    /// operation uses, so a tree keyed by a wrapper like
+    /// [`Reverse`](core::cmp::Reverse) is checked under the wrapper's
+    /// ordering rather than the inner type's.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{BalanceStrategy, Tree};
+    ///
+    /// let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+    /// for k in 0..100 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert!(tree.validate());
+    /// ```
+    pub fn validate(&self) -> bool
+    where
+        K: Ord,
+    {
+        valid_shape(&self.root, self.strategy).is_some() && keys_ordered(&self.root, None, None)
+    }
+
+    /// Panics if the `Tree` fails [`validate`](Tree::validate), so that
+    /// corruption surfaces at the mutation that caused it.
+    ///
+    /// This compiles to nothing unless debug assertions are enabled.
+    fn debug_assert_valid(&self)
+    where
+        K: Ord,
+    {
+        debug_assert!(self.validate(), "tree invariants violated");
+    }
+
+    /// Panics unless the `Tree` satisfies the red-black invariants: a
+    /// black root, no red node with a red child, and the same number of
+    /// black nodes on every path down to an empty child.
+    ///
+    /// Black heights are counted afresh rather than read from the cached
+    /// fields.
+    #[cfg(debug_assertions)]
+    fn assert_rb_invariants(&self) {
+        fn black_nodes<K, V>(root: &Option<Box<Node<K, V>>>) -> usize {
+            let node = match root {
+                None => return 0,
+                Some(node) => node,
+            };
+            assert!(
+                !(node.red && (is_red(&node.l) || is_red(&node.r))),
+                "red node with a red child"
+            );
+            let (l, r) = (black_nodes(&node.l), black_nodes(&node.r));
+            assert_eq!(l, r, "paths differ in black height");
+            l + usize::from(!node.red)
+        }
+        assert!(!is_red(&self.root), "red root");
+        black_nodes(&self.root);
+    }
+
+    /// Rebuilds the `Tree` balanced if `height() / log2(len())` exceeds
+    /// `threshold`, returning whether it was rebuilt.
+    ///
+    /// A threshold around 2.0 allows some slack before paying for a
+    /// rebuild. Trees with fewer than two entries are never rebuilt.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..64 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert!(tree.rebuild_if_unbalanced(2.0));
+    /// assert_eq!(tree.height(), 6);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn rebuild_if_unbalanced(&mut self, threshold: f64) -> bool
+    where
+        K: Ord,
+    {
+        let len = self.len();
+        if len < 2 || self.height() as f64 / (len as f64).log2() <= threshold {
+            return false;
+        }
+        let entries = self.take_sorted();
+        self.rebuild_from(entries);
+        true
+    }
+
+    /// Rebalances the `Tree` in place with the Day-Stout-Warren
+    /// algorithm, leaving it with the minimum possible height.
+    ///
+    /// The tree is first flattened into a right-leaning vine with right
+    /// rotations, then folded back up with rounds of left rotations. This
+    /// takes O(n) time and allocates nothing, which suits trees that are
+    /// bulk loaded without balancing and then only queried.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..1000 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.height(), 999);
+    /// tree.rebalance();
+    /// assert_eq!(tree.height(), 9);
+    /// ```
+    pub fn rebalance(&mut self)
+    where
+        K: Ord,
+    {
+        let mut slot = &mut self.root;
+        while let Some(node) = slot {
+            if node.l.is_some() {
+                rotate_r(slot);
+            } else {
+                slot = &mut slot.as_mut().expect("matched above").r;
+            }
+        }
+
+        // Fold the bottom level's worth of nodes first, so that every
+        // later round halves a vine of 2^k - 1 nodes.
+        let len = size(&self.root);
+        let full = (1 << (len + 1).ilog2()) - 1;
+        fold_vine(&mut self.root, len - full);
+        let mut spine = full;
+        while spine > 1 {
+            spine /= 2;
+            fold_vine(&mut self.root, spine);
+        }
+
+        // Rotations keep sizes right but leave ancestors' heights stale.
+        refresh_subtree(&mut self.root);
+        if self.strategy == BalanceStrategy::RedBlack {
+            let depth = height(&self.root).saturating_sub(1);
+            paint_level_red(&mut self.root, depth);
+            blacken_root(&mut self.root, self.strategy);
+        }
+        self.debug_assert_valid();
+    }
+
+    /// Returns an iterator that walks this tree and `other` together in
+    /// key order, reporting for each key which trees hold it.
+    ///
+    /// The values of the two trees may have different types. This takes
+    /// O(n + m) and allocates only the two traversal stacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{MergeItem, Tree};
+    ///
+    /// let mut old = Tree::with(1, 'a');
+    /// old.insert(2, 'b');
+    /// let new = Tree::with(2, "two");
+    /// let diff: Vec<_> = old.merge_join(&new).collect();
+    /// assert_eq!(
+    ///     diff,
+    ///     vec![MergeItem::Left(&1, &'a'), MergeItem::Both(&2, &'b', &"two")]
+    /// );
+    /// ```
+    pub fn merge_join<'a, W>(
+        &'a self,
+        other: &'a Tree<K, W>,
+    ) -> impl Iterator<Item = MergeItem<'a, K, V, W>>
+    where
+        K: Ord,
+    {
+        let mut a = self.iter().peekable();
+        let mut b = other.iter().peekable();
+        core::iter::from_fn(move || {
+            let ordering = match (a.peek(), b.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((ka, _)), Some((kb, _))) => ka.cmp(kb),
+            };
+            Some(match ordering {
+                Ordering::Less => {
+                    let (k, v) = a.next()?;
+                    MergeItem::Left(k, v)
+                }
+                Ordering::Greater => {
+                    let (k, w) = b.next()?;
+                    MergeItem::Right(k, w)
+                }
+                Ordering::Equal => {
+                    let (k, v) = a.next()?;
+                    let (_, w) = b.next()?;
+                    MergeItem::Both(k, v, w)
+                }
+            })
+        })
+    }
+
+    /// Adds each entry of `defaults` whose key is absent from `self`,
+    /// leaving existing values intact.
+    ///
+    /// Both trees are walked once in order and the result is rebuilt
+    /// balanced, in O(n + m).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut config = Tree::with("color", "red");
+    /// let mut defaults = Tree::with("color", "blue");
+    /// defaults.insert("size", "large");
+    /// config.fill_defaults(defaults);
+    /// assert_eq!(config.get("color"), Some(&"red"));
+    /// assert_eq!(config.get("size"), Some(&"large"));
+    /// ```
+    pub fn fill_defaults(&mut self, defaults: Tree<K, V>)
+    where
+        K: Ord,
+    {
+        let mut entries = Vec::with_capacity(self.len() + defaults.len());
+        let mut user = self.take_sorted().into_iter().peekable();
+        let mut defaults = IntoIter::new(defaults).peekable();
+        loop {
+            let ordering = match (user.peek(), defaults.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((ku, _)), Some((kd, _))) => ku.cmp(kd),
+            };
+            match ordering {
+                Ordering::Less => entries.extend(user.next()),
+                Ordering::Greater => entries.extend(defaults.next()),
+                Ordering::Equal => {
+                    entries.extend(user.next());
+                    defaults.next();
+                }
+            }
+        }
+        self.rebuild_from(entries);
+    }
+
+    /// Serializes the `Tree` into a compact byte buffer that preserves
+    /// its exact shape.
+    ///
+    /// The first byte tags the [`BalanceStrategy`]. Nodes follow in
+    /// pre-order, each preceded by a marker byte, `2` for a red node and
+    /// `1` for any other, with a `0` byte marking every absent child.
+    /// Keys and values use their little-endian [`FixedBytes`] encoding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree: Tree<u32, u32> = Tree::with(2, 20);
+    /// tree.insert(1, 10);
+    /// let bytes = tree.to_preorder_bytes();
+    /// assert_eq!(Tree::from_preorder_bytes(&bytes), Some(tree));
+    /// ```
+    pub fn to_preorder_bytes(&self) -> Vec<u8>
+    where
+        K: FixedBytes,
+        V: FixedBytes,
+    {
+        let mut bytes = vec![self.strategy.to_byte()];
+        let mut stack = vec![self.root.as_deref()];
+        while let Some(node) = stack.pop() {
+            match node {
+                None => bytes.push(0),
+                Some(node) => {
+                    bytes.push(if node.red { 2 } else { 1 });
+                    node.k.write_bytes(&mut bytes);
+                    node.v.write_bytes(&mut bytes);
+                    stack.push(node.r.as_deref());
+                    stack.push(node.l.as_deref());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Returns a hash of the `Tree`'s keys and shape.
+    ///
+    /// Keys are hashed in pre-order with a marker for every present and
+    /// absent child, so trees holding the same keys in different shapes
+    /// hash differently. Values are not hashed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut leaning = Tree::with(1, ());
+    /// leaning.insert(2, ());
+    /// let mut other = Tree::with(2, ());
+    /// other.insert(1, ());
+    /// assert_ne!(leaning.structural_hash(), other.structural_hash());
+    /// assert_eq!(leaning.structural_hash(), leaning.clone().structural_hash());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn structural_hash(&self) -> u64
+    where
+        K: Hash,
+    {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        let mut stack = vec![self.root.as_deref()];
+        while let Some(node) = stack.pop() {
+            match node {
+                None => hasher.write_u8(0),
+                Some(node) => {
+                    hasher.write_u8(1);
+                    node.k.hash(&mut hasher);
+                    stack.push(node.r.as_deref());
+                    stack.push(node.l.as_deref());
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Reconstructs a `Tree` from the output of
+    /// [`to_preorder_bytes`](Tree::to_preorder_bytes).
+    ///
+    /// The original shape, strategy and node colors are restored as is,
+    /// without rebalancing. Returns `None` if `bytes` is truncated, has
+    /// trailing data, or doesn't describe a tree that passes
+    /// [`validate`](Tree::validate) under its strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[0, 0]), Some(Tree::new()));
+    /// assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[0, 1, 7]), None);
+    /// ```
+    pub fn from_preorder_bytes(bytes: &[u8]) -> Option<Self>
+    where
+        K: Ord + FixedBytes,
+        V: FixedBytes,
+    {
+        let (&tag, mut rest) = bytes.split_first()?;
+        let strategy = BalanceStrategy::from_byte(tag)?;
+        let colored = strategy == BalanceStrategy::RedBlack;
+        let mut tokens = Vec::new();
+        // Each token fills one open child slot, and each node opens two.
+        let mut open = 1;
+        while open > 0 {
+            let (&marker, tail) = rest.split_first()?;
+            rest = tail;
+            match marker {
+                0 => {
+                    open -= 1;
+                    tokens.push(None);
+                }
+                1 | 2 if (marker == 1 || colored) && rest.len() >= K::SIZE + V::SIZE => {
+                    let (k, tail) = rest.split_at(K::SIZE);
+                    let (v, tail) = tail.split_at(V::SIZE);
+                    rest = tail;
+                    open += 1;
+                    tokens.push(Some((K::read_bytes(k), V::read_bytes(v), marker == 2)));
+                }
+                _ => return None,
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        let mut subtrees: Vec<Option<Box<Node<K, V>>>> = Vec::new();
+        for token in tokens.into_iter().rev() {
+            match token {
+                None => subtrees.push(None),
+                Some((k, v, red)) => {
+                    let l = subtrees.pop()?;
+                    let r = subtrees.pop()?;
+                    let mut node = Box::new(Node::with_children(k, v, l, r));
+                    node.paint(red);
+                    subtrees.push(Some(node));
+                }
+            }
+        }
+        let tree = Tree {
+            root: subtrees.pop()?,
+            ..Self::with_strategy(strategy)
+        };
+        if tree.validate() {
+            Some(tree)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of entries whose values fall within `range`.
+    ///
+    /// The tree is ordered by key, not value, so every value is visited
+    /// once and tested against `range`, however few of them match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, k * k);
+    /// }
+    /// assert_eq!(tree.count_values_in(10..50), 4);
+    /// ```
+    pub fn count_values_in<R>(&self, range: R) -> usize
+    where
+        V: Ord,
+        R: RangeBounds<V>,
+    {
+        self.iter().filter(|(_, v)| range.contains(*v)).count()
+    }
+
+    /// Returns every key in `lo..=hi` that the `Tree` doesn't contain,
+    /// in ascending order.
+    ///
+    /// `succ` must return the key immediately after its argument, for
+    /// example `|&k| k + 1` for integers. It is never called on `hi`, so
+    /// `hi` may be the type's maximum.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in &[1, 2, 4, 5] {
+    ///     tree.insert(*k, ());
+    /// }
+    /// assert_eq!(tree.missing_in_range(1, 5, |&k| k + 1), vec![3]);
+    /// ```
+    pub fn missing_in_range<F>(&self, lo: K, hi: K, succ: F) -> Vec<K>
+    where
+        K: Ord + Clone,
+        F: Fn(&K) -> K,
+    {
+        let mut missing = Vec::new();
+        let mut present = NodeIter::from_bound(self, Bound::Included(&lo)).peekable();
+        let mut expected = lo;
+        while expected <= hi {
+            while present.next_if(|node| node.k < expected).is_some() {}
+            if present.next_if(|node| node.k == expected).is_none() {
+                missing.push(expected.clone());
+            }
+            if expected == hi {
+                break;
+            }
+            expected = succ(&expected);
+        }
+        missing
+    }
+
+    /// Returns each key in sorted order, paired with the running total
+    /// of the values up to and including its own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// tree.insert("a", 2);
+    /// tree.insert("b", 5);
+    /// tree.insert("c", 1);
+    /// assert_eq!(tree.prefix_sums(), vec![(&"a", 2), (&"b", 7), (&"c", 8)]);
+    /// ```
+    pub fn prefix_sums(&self) -> Vec<(&K, V)>
+    where
+        V: Clone + AddAssign,
+    {
+        let mut sums = Vec::with_capacity(size(&self.root));
+        let mut total: Option<V> = None;
+        for (k, v) in self.iter() {
+            match total.as_mut() {
+                Some(total) => *total += v.clone(),
+                None => total = Some(v.clone()),
+            }
+            sums.push((k, total.clone().expect("set above")));
+        }
+        sums
+    }
+
+    /// Returns the sum of `weight(v)` over every value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// tree.insert("a", 2);
+    /// tree.insert("b", 5);
+    /// assert_eq!(tree.total_weight(|&w| w), 7);
+    /// ```
+    pub fn total_weight<F: Fn(&V) -> u64>(&self, weight: F) -> u64 {
+        self.iter().map(|(_, v)| weight(v)).sum()
+    }
+
+    /// Returns the first entry, in key order, at which the running sum
+    /// of `weight(v)` exceeds `target`.
+    ///
+    /// Drawing `target` uniformly from `0..total_weight(weight)` picks
+    /// each entry with probability proportional to its weight. Returns
+    /// `None` if `target` is at least the total weight.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// tree.insert("a", 2);
+    /// tree.insert("b", 5);
+    /// assert_eq!(tree.weighted_select(1, |&w| w), Some((&"a", &2)));
+    /// assert_eq!(tree.weighted_select(2, |&w| w), Some((&"b", &5)));
+    /// assert_eq!(tree.weighted_select(7, |&w| w), None);
+    /// ```
+    pub fn weighted_select<F: Fn(&V) -> u64>(&self, target: u64, weight: F) -> Option<(&K, &V)> {
+        let mut sum = 0u64;
+        self.iter().find(|(_, v)| {
+            sum += weight(v);
+            sum > target
+        })
+    }
+
+    /// Captures a snapshot of the `Tree` that can later be handed to
+    /// [`restore`](Tree::restore) to undo edits.
+    ///
+    /// The snapshot is a full clone, so taking one costs O(n) time and
+    /// memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// let cp = tree.checkpoint();
+    /// tree.insert(2, 'b');
+    /// tree.restore(cp);
+    /// assert_eq!(tree.get(&2), None);
+    /// ```
+    pub fn checkpoint(&self) -> Checkpoint<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Checkpoint(self.clone())
+    }
+
+    /// Replaces the `Tree`'s contents with those captured by `cp`.
+    pub fn restore(&mut self, cp: Checkpoint<K, V>) {
+        *self = cp.0;
+    }
+
+    /// Returns the number of nodes at each depth of the tree.
+    ///
+    /// Index `d` of the returned vector holds the count of nodes at
+    /// depth `d`, with the root at depth 0. A balanced tree roughly
+    /// doubles at each level while a degenerate one is all 1s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, ());
+    /// tree.insert(1, ());
+    /// tree.insert(3, ());
+    /// assert_eq!(tree.depth_histogram(), vec![1, 2]);
+    /// ```
+    pub fn depth_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        let mut stack: Vec<(&Node<K, V>, usize)> =
+            self.root.as_deref().map(|n| (n, 0)).into_iter().collect();
+        while let Some((node, depth)) = stack.pop() {
+            if histogram.len() <= depth {
+                histogram.push(0);
+            }
+            histogram[depth] += 1;
+            for child in [node.l.as_deref(), node.r.as_deref()].iter().flatten() {
+                stack.push((child, depth + 1));
+            }
+        }
+        histogram
+    }
+
+    /// Returns the key of the node with the largest absolute balance
+    /// factor, the difference between its left and right subtree
+    /// heights.
+    ///
+    /// This is where a rotation would help the most. Ties go to the
+    /// node that comes first in key order. Returns `None` if the tree
+    /// is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..5 {
+    ///     tree.insert(k, ());
+    /// }
+    /// assert_eq!(tree.most_unbalanced_key(), Some(&0));
+    /// ```
+    pub fn most_unbalanced_key(&self) -> Option<&K>
+    where
+        K: Ord,
+    {
+        self.root.as_ref().map(|node| (node.most_unbalanced().1).1)
+    }
+
+    /// Consumes the `Tree`, moving its entries into a single boxed slice
+    /// in sorted order.
+    ///
+    /// The slice can be searched with `<[_]>::binary_search_by`, or
+    /// wrapped with [`into_sorted_slice`](Tree::into_sorted_slice).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(2, 'b');
+    /// tree.insert(1, 'a');
+    /// let entries = tree.into_boxed_sorted();
+    /// assert_eq!(&*entries, &[(1, 'a'), (2, 'b')]);
+    /// assert_eq!(entries.binary_search_by(|(k, _)| k.cmp(&2)), Ok(1));
+    /// ```
+    pub fn into_boxed_sorted(self) -> Box<[(K, V)]> {
+        self.into_sorted_vec().into_boxed_slice()
+    }
+
+    /// Consumes the `Tree`, returning a read-only [`SortedSlice`] of
+    /// its entries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("b", 2);
+    /// tree.insert("a", 1);
+    /// let frozen = tree.into_sorted_slice();
+    /// assert_eq!(frozen.get("b"), Some(&2));
+    /// ```
+    pub fn into_sorted_slice(self) -> SortedSlice<K, V> {
+        SortedSlice(self.into_boxed_sorted())
+    }
+
+    /// Moves every entry with a key at or above `k` into a new `Tree`,
+    /// returning it.
+    ///
+    /// The tree is cut along the search path for `k` and both halves keep
+    /// the strategy, so this takes O(height) time and allocates nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..5 {
+    ///     tree.insert(k, ());
+    /// }
+    /// let upper = tree.split_off(&3);
+    /// assert_eq!(tree.keys().collect::<Vec<_>>(), vec![&0, &1, &2]);
+    /// assert_eq!(upper.keys().collect::<Vec<_>>(), vec![&3, &4]);
+    /// ```
+    pub fn split_off<Q>(&mut self, k: &Q) -> Tree<K, V>
+    where
+        K: Ord + Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (rank, _) = self.search_rank(k);
+        self.split_off_rank(rank)
+    }
+
+    /// Keeps the first `n` entries in key order and moves the rest into a
+    /// new `Tree`, returning it.
+    ///
+    /// This is [`split_off`](Tree::split_off) at the `n`-th key, which
+    /// is found from the cached subtree sizes rather than by comparing
+    /// keys, so it also takes O(height) time and allocates nothing. If
+    /// `n` is at least `len()`, the returned tree is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in "abcde".chars() {
+    ///     tree.insert(k, ());
+    /// }
+    /// let rest = tree.split_at_index(2);
+    /// assert_eq!(tree.keys().collect::<String>(), "ab");
+    /// assert_eq!(rest.keys().collect::<String>(), "cde");
+    /// ```
+    pub fn split_at_index(&mut self, n: usize) -> Tree<K, V>
+    where
+        K: Ord,
+    {
+        self.split_off_rank(n)
+    }
+
+    /// Consumes the `Tree`, partitioning it into `n` balanced trees over
+    /// contiguous, disjoint key ranges.
+    ///
+    /// Part sizes differ by at most one, and the parts are returned in
+    /// key order. If `n` exceeds the number of entries, the trailing
+    /// parts are empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, ());
+    /// }
+    /// let parts = tree.split_into(3);
+    /// let lens: Vec<usize> = parts.iter().map(Tree::len).collect();
+    /// assert_eq!(lens, vec![4, 3, 3]);
+    /// ```
+    pub fn split_into(self, n: usize) -> Vec<Tree<K, V>>
+    where
+        K: Ord,
+    {
+        assert!(n > 0, "cannot split a tree into zero parts");
+        let len = self.len();
+        let strategy = self.strategy;
+        let mut entries = IntoIter::new(self);
+        (0..n)
+            .map(|i| {
+                let part_len = len / n + usize::from(i < len % n);
+                Tree {
+                    root: build_balanced_for(&mut entries, part_len, strategy),
+                    ..Tree::with_strategy(strategy)
+                }
+            })
+            .collect()
+    }
+
+    /// Consumes the `Tree`, returning a balanced tree with each key
+    /// replaced by `f(&key)`.
+    ///
+    /// `f` is called on the keys in their old sorted order. If it maps
+    /// several keys to the same new key, the value of the last of them
+    /// in that order wins.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// tree.insert(2, 'b');
+    /// let scaled = tree.remap_keys(|&k| k * 10);
+    /// assert_eq!(scaled.get(&20), Some(&'b'));
+    /// ```
+    pub fn remap_keys<K2: Ord, F: FnMut(&K) -> K2>(self, mut f: F) -> Tree<K2, V> {
+        let strategy = self.strategy;
+        let mut entries: Vec<(K2, V)> = IntoIter::new(self).map(|(k, v)| (f(&k), v)).collect();
+        sort_keep_last(&mut entries);
+        let mut tree = Tree::with_strategy(strategy);
+        tree.rebuild_from(entries);
+        tree
+    }
+
+    /// Consumes the `Tree`, returning a tree that maps each group key
+    /// `key_fn(&k, &v)` to the values in that group.
+    ///
+    /// Each group's values are in the order of their old keys. The new
+    /// tree keeps the strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 1..=6 {
+    ///     tree.insert(k, k * 10);
+    /// }
+    /// let parity = tree.group_by(|&k, _| k % 2 == 0);
+    /// assert_eq!(parity.get(&true), Some(&vec![20, 40, 60]));
+    /// assert_eq!(parity.get(&false), Some(&vec![10, 30, 50]));
+    /// ```
+    pub fn group_by<G: Ord, F: FnMut(&K, &V) -> G>(self, mut key_fn: F) -> Tree<G, Vec<V>> {
+        let mut groups: Tree<G, Vec<V>> = Tree::with_strategy(self.strategy);
+        for (k, v) in IntoIter::new(self) {
+            groups.entry(key_fn(&k, &v)).or_default().push(v);
+        }
+        groups
+    }
+
+    /// Returns a tree that maps each bucket `f(&k, &v)` to the number of
+    /// entries that fall in it.
+    ///
+    /// The new tree keeps the strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 1..=5 {
+    ///     tree.insert(k, ());
+    /// }
+    /// let parity = tree.counts_by(|&k, _| k % 2 == 0);
+    /// assert_eq!(parity.get(&true), Some(&2));
+    /// assert_eq!(parity.get(&false), Some(&3));
+    /// ```
+    pub fn counts_by<B: Ord, F: Fn(&K, &V) -> B>(&self, f: F) -> Tree<B, usize> {
+        let mut counts = Tree::with_strategy(self.strategy);
+        for (k, v) in self.iter() {
+            *counts.entry(f(k, v)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Consumes the `Tree`, returning a new tree keyed by the old
+    /// values.
+    ///
+    /// Values are expected to be unique. If several keys map to the
+    /// same value, the last one in key order wins. The new tree is built
+    /// balanced and keeps the strategy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with(1, 'a');
+    /// tree.insert(2, 'b');
+    /// let inverted = tree.invert();
+    /// assert_eq!(inverted.get(&'b'), Some(&2));
+    /// ```
+    pub fn invert(self) -> Tree<V, K>
+    where
+        V: Ord,
+    {
+        let strategy = self.strategy;
+        let mut entries: Vec<(V, K)> = IntoIter::new(self).map(|(k, v)| (v, k)).collect();
+        sort_keep_last(&mut entries);
+        let mut inverted = Tree::with_strategy(strategy);
+        inverted.rebuild_from(entries);
+        inverted
+    }
+
+    /// Builds a `Tree` of minimum height from entries in any order.
+    ///
+    /// The entries are collected and sorted first, so this takes
+    /// O(n log n). If several entries share a key, the last one wins, as
+    /// with repeated [`insert`](Tree::insert)s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let tree = Tree::from_iter_balanced(vec![(3, 'c'), (1, 'a'), (2, 'b'), (1, 'z')]);
+    /// assert_eq!(tree.height(), 1);
+    /// assert_eq!(tree.get(&1), Some(&'z'));
+    /// ```
+    pub fn from_iter_balanced<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self
+    where
+        K: Ord,
+    {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        sort_keep_last(&mut entries);
+        Self::from_sorted(entries)
+    }
+
+    /// Builds a balanced `Tree` from entries sorted by unique key.
+    fn from_sorted(entries: Vec<(K, V)>) -> Self {
+        let mut tree = Self::new();
+        tree.rebuild_from(entries);
+        tree
+    }
+
+    /// Wraps `root` in a `Tree` with no balancing strategy.
+    fn from_root(root: Option<Box<Node<K, V>>>) -> Self {
+        Self {
+            root,
+            ..Self::new()
+        }
+    }
+
+    /// Moves every entry out in sorted order, leaving the `Tree` empty
+    /// but keeping its strategy.
+    fn take_sorted(&mut self) -> Vec<(K, V)> {
+        Self::from_root(self.root.take()).into_sorted_vec()
+    }
+
+    /// Replaces the contents with a balanced tree built from entries
+    /// sorted by unique key, keeping the current strategy.
+    fn rebuild_from(&mut self, entries: Vec<(K, V)>) {
+        let len = entries.len();
+        self.root = build_balanced_for(&mut entries.into_iter(), len, self.strategy);
+    }
+
+    /// Keeps the `rank` smallest entries and returns a `Tree` of the
+    /// rest, cutting along one path as by [`split_rank`].
+    fn split_off_rank(&mut self, rank: usize) -> Tree<K, V>
+    where
+        K: Ord,
+    {
+        let (mut lower, mut upper) = split_rank(self.root.take(), rank, self.strategy);
+        blacken_root(&mut lower, self.strategy);
+        blacken_root(&mut upper, self.strategy);
+        self.root = lower;
+        let upper = Tree {
+            root: upper,
+            ..Tree::with_strategy(self.strategy)
+        };
+        self.debug_assert_valid();
+        upper.debug_assert_valid();
+        upper
+    }
+
+    /// Consumes the `Tree`, returning a sorted iterator over the owned
+    /// key-value pairs that satisfy `pred`.
+    ///
+    /// Pairs that don't match are dropped as the iterator passes them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..6 {
+    ///     tree.insert(k, k.to_string());
+    /// }
+    /// let evens: Vec<(i32, String)> = tree.into_iter_filter(|k, _| k % 2 == 0).collect();
+    /// assert_eq!(evens[1], (2, String::from("2")));
+    /// ```
+    pub fn into_iter_filter<F>(self, mut pred: F) -> impl Iterator<Item = (K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        IntoIter::new(self).filter(move |(k, v)| pred(k, v))
+    }
+
+    fn nodes(&self) -> NodeIter<'_, K, V> {
+        NodeIter::new(self)
+    }
+
+    /// Returns the node for `k`, inserting one with the value from `f`
+    /// if `k` is absent, and whether the node already existed.
+    fn find_or_insert_with<F>(&mut self, k: K, f: F) -> (&mut Node<K, V>, bool)
+    where
+        K: Ord,
+        F: FnOnce() -> V,
+    {
+        let (rank, found) = self.search_rank(&k);
+        if !found {
+            let leaf = Box::new(Node::with_children(k, f(), None, None));
+            insert_at_rank(&mut self.root, rank, leaf, self.strategy);
+            blacken_root(&mut self.root, self.strategy);
+            self.debug_assert_valid();
+        }
+        let node = select_mut(&mut self.root, rank).expect("present or inserted above");
+        (node, found)
+    }
+
+    /// Returns the node for `k`, if any.
+    fn find_node<Q>(&self, k: &Q) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            curr = match node.k.borrow().cmp(k) {
+                Ordering::Greater => node.l.as_deref(),
+                Ordering::Equal => return Some(node),
+                Ordering::Less => node.r.as_deref(),
+            };
+        }
+        None
+    }
+
+    /// Returns the keys from the root down to `k`, or `None` if `k` is
+    /// absent.
+    fn path_to<Q>(&self, k: &Q) -> Option<Vec<&K>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut path = Vec::new();
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            path.push(&node.k);
+            curr = match node.k.borrow().cmp(k) {
+                Ordering::Greater => node.l.as_deref(),
+                Ordering::Equal => return Some(path),
+                Ordering::Less => node.r.as_deref(),
+            };
+        }
+        None
+    }
+
+    /// Returns the number of keys less than `k`, and whether `k` itself
+    /// is present.
+    ///
+    /// The count is `k`'s rank, or the rank it would have once
+    /// inserted, so later walks to it can descend by subtree size
+    /// without comparing keys again.
+    fn search_rank<Q>(&self, k: &Q) -> (usize, bool)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut below = 0;
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            curr = match node.k.borrow().cmp(k) {
+                Ordering::Less => {
+                    below += size(&node.l) + 1;
+                    node.r.as_deref()
+                }
+                Ordering::Equal => return (below + size(&node.l), true),
+                Ordering::Greater => node.l.as_deref(),
+            };
+        }
+        (below, false)
+    }
+
+    /// Returns the node with `rank` smaller keys, if any.
+    fn select_node(&self, mut rank: usize) -> Option<&Node<K, V>> {
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            let l_size = size(&node.l);
+            curr = match rank.cmp(&l_size) {
+                Ordering::Less => node.l.as_deref(),
+                Ordering::Equal => return Some(node),
+                Ordering::Greater => {
+                    rank -= l_size + 1;
+                    node.r.as_deref()
+                }
+            };
+        }
+        None
+    }
+
+    /// Returns the node with the smallest key at or above the lower
+    /// `bound`.
+    fn lower_bound_node<Q>(&self, bound: Bound<&Q>) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut best = None;
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            let in_bound = match bound {
+                Bound::Included(b) => node.k.borrow() >= b,
+                Bound::Excluded(b) => node.k.borrow() > b,
+                Bound::Unbounded => true,
+            };
+            if in_bound {
+                best = Some(node);
+                curr = node.l.as_deref();
+            } else {
+                curr = node.r.as_deref();
+            }
+        }
+        best
+    }
+
+    /// Returns the node with the largest key at or below the upper
+    /// `bound`.
+    fn upper_bound_node<Q>(&self, bound: Bound<&Q>) -> Option<&Node<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut best = None;
+        let mut curr = self.root.as_deref();
+        while let Some(node) = curr {
+            let in_bound = match bound {
+                Bound::Included(b) => node.k.borrow() <= b,
+                Bound::Excluded(b) => node.k.borrow() < b,
+                Bound::Unbounded => true,
+            };
+            if in_bound {
+                best = Some(node);
+                curr = node.r.as_deref();
+            } else {
+                curr = node.l.as_deref();
+            }
+        }
+        best
+    }
+
+    /// Consumes the `Tree`, returning its key-value pairs in sorted
+    /// order.
+    fn into_sorted_vec(self) -> Vec<(K, V)> {
+        let mut entries = Vec::with_capacity(size(&self.root));
+        entries.extend(IntoIter::new(self));
+        entries
+    }
+}
+
+/// Appends `item` to the `Vec` stored at `k`, creating an empty one
+/// first if `k` is absent.
+///
+/// This lets a `Tree<K, Vec<T>>` be used as a multimap.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::{push_into, Tree};
+///
+/// let mut tree = Tree::new();
+/// push_into(&mut tree, "fruit", "apple");
+/// push_into(&mut tree, "fruit", "pear");
+/// assert_eq!(tree.get("fruit"), Some(&vec!["apple", "pear"]));
+/// ```
+pub fn push_into<K: Ord, T>(tree: &mut Tree<K, Vec<T>>, k: K, item: T) {
+    let (node, _) = tree.find_or_insert_with(k, Vec::new);
+    node.v.push(item);
+}
+
+/// What an insertion did, returned by [`Tree::insert_reporting`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InsertReport<V> {
+    /// The value previously stored for the key, if any.
+    pub old: Option<V>,
+    /// The number of single rotations performed to rebalance. A double
+    /// rotation counts as two.
+    pub rotations: usize,
+    /// The tree's height afterward, as returned by [`Tree::height`].
+    pub height: usize,
+}
+
+/// A pending lookup in a `Tree`, created by [`Tree::entry_ref`].
+pub struct EntryRef<'a, K, V> {
+    tree: &'a mut Tree<K, V>,
+    key: K,
+}
+
+impl<'a, K: Ord, V> EntryRef<'a, K, V> {
+    /// Inserts `default` if the key is absent, then returns the stored
+    /// key and a mutable reference to its value.
+    pub fn or_insert(self, default: V) -> (&'a K, &'a mut V) {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `f` if the key is absent, then returns the
+    /// stored key and a mutable reference to its value.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> (&'a K, &'a mut V) {
+        let (node, _) = self.tree.find_or_insert_with(self.key, f);
+        let Node { k, v, .. } = node;
+        (k, v)
+    }
+}
+
+/// A view into a single entry of a `Tree`, created by [`Tree::entry`].
+pub enum Entry<'a, K, V> {
+    /// The key is present.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// The key is absent.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+impl<'a, K: Ord, V> Entry<'a, K, V> {
+    /// Returns the entry's key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree: Tree<&str, u32> = Tree::new();
+    /// assert_eq!(tree.entry("owl").key(), &"owl");
+    /// ```
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, then returns a mutable
+    /// reference to the value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut counts = Tree::new();
+    /// for word in "a b a".split(' ') {
+    ///     *counts.entry(word).or_insert(0) += 1;
+    /// }
+    /// assert_eq!(counts.get("a"), Some(&2));
+    /// ```
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `f` if the entry is vacant, then returns a
+    /// mutable reference to the value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// tree.entry("owl").or_insert_with(Vec::new).push(1);
+    /// assert_eq!(tree.get("owl"), Some(&vec![1]));
+    /// ```
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(f()),
+        }
+    }
+
+    /// Inserts `V::default()` if the entry is vacant, then returns a
+    /// mutable reference to the value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree: Tree<&str, u32> = Tree::new();
+    /// assert_eq!(tree.entry("owl").or_default(), &mut 0);
+    /// ```
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Runs `f` on the value if the entry is occupied, then returns the
+    /// entry for further chaining.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::with("owl", 1);
+    /// tree.entry("owl").and_modify(|v| *v += 1).or_insert(0);
+    /// tree.entry("bat").and_modify(|v| *v += 1).or_insert(0);
+    /// assert_eq!(tree.get("owl"), Some(&2));
+    /// assert_eq!(tree.get("bat"), Some(&0));
+    /// ```
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+/// An entry whose key is present in the `Tree`.
+pub struct OccupiedEntry<'a, K, V> {
+    node: &'a mut Node<K, V>,
+}
+
+impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
+    /// Returns the stored key.
+    pub fn key(&self) -> &K {
+        &self.node.k
+    }
+
+    /// Returns a reference to the value.
+    pub fn get(&self) -> &V {
+        &self.node.v
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.node.v
+    }
+
+    /// Converts the entry into a mutable reference to the value with
+    /// the lifetime of the `Tree`.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.node.v
+    }
+
+    /// Replaces the value, returning the old one.
+    pub fn insert(&mut self, v: V) -> V {
+        mem::replace(&mut self.node.v, v)
+    }
+
+    /// Replaces the stored key with `new_key`, returning the old key.
+    ///
+    /// This canonicalizes the stored key without touching the tree's
+    /// shape, so `new_key` must compare equal to the old key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{Entry, Tree};
+    /// use std::cmp::Ordering;
+    ///
+    /// // A name compared without regard to case.
+    /// #[derive(Debug, Eq)]
+    /// struct Name(&'static str);
+    ///
+    /// impl Ord for Name {
+    ///     fn cmp(&self, other: &Self) -> Ordering {
+    ///         self.0.to_lowercase().cmp(&other.0.to_lowercase())
+    ///     }
+    /// }
+    /// # impl PartialOrd for Name {
+    /// #     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    /// #         Some(self.cmp(other))
+    /// #     }
+    /// # }
+    /// # impl PartialEq for Name {
+    /// #     fn eq(&self, other: &Self) -> bool {
+    /// #         self.cmp(other) == Ordering::Equal
+    /// #     }
+    /// # }
+    ///
+    /// let mut tree = Tree::with(Name("ADA"), 1815);
+    /// if let Entry::Occupied(e) = tree.entry(Name("Ada")) {
+    ///     assert_eq!(e.replace_key(Name("Ada")).0, "ADA");
+    /// }
+    /// assert_eq!(tree.iter().next().unwrap().0 .0, "Ada");
+    /// ```
+    pub fn replace_key(self, new_key: K) -> K {
+        debug_assert!(
+            new_key.cmp(&self.node.k) == Ordering::Equal,
+            "replacement key must compare equal to the stored key"
+        );
+        mem::replace(&mut self.node.k, new_key)
+    }
+}
+
+/// An entry whose key is absent from the `Tree`.
+pub struct VacantEntry<'a, K, V> {
+    tree: &'a mut Tree<K, V>,
+    key: K,
+    /// The key's rank once inserted.
+    rank: usize,
+}
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+    /// Returns the key that would be inserted.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes back ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts `v` under the entry's key and returns a mutable
+    /// reference to it.
+    ///
+    /// The position was found when the entry was created, so this
+    /// descends by subtree size without comparing keys again.
+    pub fn insert(self, v: V) -> &'a mut V {
+        let tree = self.tree;
+        let leaf = Box::new(Node::with_children(self.key, v, None, None));
+        insert_at_rank(&mut tree.root, self.rank, leaf, tree.strategy);
+        blacken_root(&mut tree.root, tree.strategy);
+        tree.debug_assert_valid();
+        &mut select_mut(&mut tree.root, self.rank)
+            .expect("inserted above")
+            .v
+    }
+}
+
+/// Spare node allocations, reused by trees through [`Tree::with_pool`]
+/// to cut down on allocator calls.
+///
+/// The pool holds empty allocations, one per node. Building and tearing
+/// down many small trees through one pool allocates only when the pool
+/// runs dry.
+pub struct NodePool<K, V> {
+    free: Vec<Box<MaybeUninit<Node<K, V>>>>,
+}
+
+impl<K, V> Default for NodePool<K, V> {
+    fn default() -> Self {
+        Self { free: Vec::new() }
+    }
+}
+
+impl<K, V> NodePool<K, V> {
+    /// Creates an empty `NodePool`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a `NodePool` holding `n` spare node allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::NodePool;
+    ///
+    /// let pool: NodePool<u32, u32> = NodePool::with_capacity(64);
+    /// assert_eq!(pool.len(), 64);
+    /// ```
+    pub fn with_capacity(n: usize) -> Self {
+        Self {
+            free: (0..n).map(|_| Box::new(MaybeUninit::uninit())).collect(),
+        }
+    }
+
+    /// Returns the number of spare node allocations.
+    pub fn len(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Returns `true` if the pool has no spare allocations.
+    pub fn is_empty(&self) -> bool {
+        self.free.is_empty()
+    }
+
+    /// Drops every entry of `tree`, keeping its node allocations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::{NodePool, Tree};
+    ///
+    /// let mut tree = Tree::with(1, ());
+    /// tree.insert(2, ());
+    /// let mut pool = NodePool::new();
+    /// pool.recycle(tree);
+    /// assert_eq!(pool.len(), 2);
+    /// ```
+    pub fn recycle(&mut self, mut tree: Tree<K, V>) {
+        self.recycle_subtree(tree.root.take());
+    }
+
+    /// Releases every node under `root` without allocating, by rotating
+    /// left children up until the top node can be detached.
+    fn recycle_subtree(&mut self, mut curr: Option<Box<Node<K, V>>>) {
+        while let Some(mut node) = curr {
+            if let Some(mut l) = node.l.take() {
+                node.l = l.r.take();
+                l.r = Some(node);
+                curr = Some(l);
+            } else {
+                curr = node.r.take();
+                self.release(node);
+            }
+        }
+    }
+
+    /// Moves `k` and `v` into a spare allocation, or a new one if the
+    /// pool is empty, as a leaf.
+    fn acquire(&mut self, k: K, v: V) -> Box<Node<K, V>> {
+        let slot = self
+            .free
+            .pop()
+            .unwrap_or_else(|| Box::new(MaybeUninit::uninit()));
+        Box::write(slot, Node::with_children(k, v, None, None))
+    }
+
+    /// Moves the entry out of the childless `node`, keeping its
+    /// allocation.
+    fn release(&mut self, node: Box<Node<K, V>>) -> (K, V) {
+        debug_assert!(node.l.is_none() && node.r.is_none());
+        let raw = Box::into_raw(node);
+        // SAFETY: `raw` came from a `Box`, so it is valid to read once.
+        // After the read the allocation is logically uninitialized, and
+        // `MaybeUninit<Node>` has the same layout as `Node`, so it can
+        // be owned again as a box of one.
+        let (node, slot) = unsafe {
+            (
+                raw.read(),
+                Box::from_raw(raw.cast::<MaybeUninit<Node<K, V>>>()),
+            )
+        };
+        self.free.push(slot);
+        (node.k, node.v)
+    }
+}
+
+/// A `Tree` paired with a [`NodePool`], created by [`Tree::with_pool`].
+///
+/// Inserting takes node allocations from the pool, and removing or
+/// clearing returns them. Read-only `Tree` methods are available
+/// through `Deref`.
+pub struct PooledTree<'a, K, V> {
+    tree: &'a mut Tree<K, V>,
+    pool: &'a mut NodePool<K, V>,
+}
+
+impl<'a, K: Ord, V> PooledTree<'a, K, V> {
+    /// Inserts a key-value pair like [`Tree::insert`], taking the node
+    /// from the pool.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let (rank, found) = self.tree.search_rank(&k);
+        if found {
+            let node = select_mut(&mut self.tree.root, rank).expect("found above");
+            return Some(mem::replace(&mut node.v, v));
+        }
+        let leaf = self.pool.acquire(k, v);
+        insert_at_rank(&mut self.tree.root, rank, leaf, self.tree.strategy);
+        blacken_root(&mut self.tree.root, self.tree.strategy);
+        self.tree.debug_assert_valid();
+        None
+    }
+
+    /// Removes the entry for `k` like [`Tree::remove`], returning its
+    /// node to the pool.
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let node = remove_node(&mut self.tree.root, k, self.tree.strategy)?;
+        blacken_root(&mut self.tree.root, self.tree.strategy);
+        self.tree.debug_assert_valid();
+        Some(self.pool.release(node).1)
+    }
+
+    /// Removes every entry, returning all nodes to the pool.
+    pub fn clear(&mut self) {
+        self.pool.recycle_subtree(self.tree.root.take());
+    }
+}
+
+impl<'a, K, V> Deref for PooledTree<'a, K, V> {
+    type Target = Tree<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        self.tree
+    }
+}
+
+/// Entries frozen into one contiguous allocation, sorted by key,
+/// created by [`Tree::into_sorted_slice`].
+///
+/// Lookups are binary searches, so they cost O(log n) like a balanced
+/// `Tree` without any per-node overhead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SortedSlice<K, V>(Box<[(K, V)]>);
+
+impl<K, V> SortedSlice<K, V> {
+    /// Returns a reference to the value for `k`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let frozen = Tree::with("cow", "moo").into_sorted_slice();
+    /// assert_eq!(frozen.get("cow"), Some(&"moo"));
+    /// assert_eq!(frozen.get("pig"), None);
+    /// ```
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let i = self
+            .0
+            .binary_search_by(|(key, _)| key.borrow().cmp(k))
+            .ok()?;
+        Some(&self.0[i].1)
+    }
+
+    /// Returns the entries whose keys fall within `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binsearchtree::Tree;
+    ///
+    /// let mut tree = Tree::new();
+    /// for k in 0..10 {
+    ///     tree.insert(k, ());
+    /// }
+    /// let frozen = tree.into_sorted_slice();
+    /// assert_eq!(frozen.range(3..5), &[(3, ()), (4, ())]);
+    /// ```
+    pub fn range<Q, R>(&self, range: R) -> &[(K, V)]
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let start = self.0.partition_point(|(k, _)| match range.start_bound() {
+            Bound::Included(b) => k.borrow() < b,
+            Bound::Excluded(b) => k.borrow() <= b,
+            Bound::Unbounded => false,
+        });
+        let end = self.0.partition_point(|(k, _)| match range.end_bound() {
+            Bound::Included(b) => k.borrow() <= b,
+            Bound::Excluded(b) => k.borrow() < b,
+            Bound::Unbounded => true,
+        });
+        &self.0[start..end.max(start)]
+    }
+
+    /// Returns all entries in sorted order.
+    pub fn as_slice(&self) -> &[(K, V)] {
+        &self.0
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A snapshot of a `Tree`, created by [`Tree::checkpoint`].
+#[derive(Debug, Clone)]
+pub struct Checkpoint<K, V>(Tree<K, V>);
+
+/// Fixed-size values with a little-endian byte encoding, used by
+/// [`Tree::to_preorder_bytes`] and [`Tree::from_preorder_bytes`].
+pub trait FixedBytes: Sized {
+    /// The number of bytes in the encoding.
+    const SIZE: usize;
+
+    /// Appends this value's encoding to `out`.
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    /// Decodes a value from exactly `SIZE` bytes.
+    fn read_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_fixed_bytes {
+    ($($t:ty),*) => {
+        $(
+            impl FixedBytes for $t {
+                const SIZE: usize = mem::size_of::<$t>();
+
+                fn write_bytes(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn read_bytes(bytes: &[u8]) -> Self {
+                    let mut buf = [0; mem::size_of::<$t>()];
+                    buf.copy_from_slice(bytes);
+                    <$t>::from_le_bytes(buf)
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_bytes!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Which tree, or trees, a key came from in [`merge`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MergeSide<T> {
+    /// The key is only in the left tree.
+    Left(T),
+    /// The key is only in the right tree.
+    Right(T),
+    /// The key is in both trees.
+    Both(T, T),
+}
+
+/// One key of a [`Tree::merge_join`], with the value from each tree
+/// that has it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MergeItem<'a, K, V, W> {
+    /// The key is only in the left tree.
+    Left(&'a K, &'a V),
+    /// The key is only in the right tree.
+    Right(&'a K, &'a W),
+    /// The key is in both trees.
+    Both(&'a K, &'a V, &'a W),
+}
+
+/// Returns an ordered merge of the key-value pairs of `a` and `b`,
+/// tagging each key with the side it came from.
+///
+/// This is the primitive underlying union, intersection and join, and
+/// it walks both trees once in O(n + m).
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::{merge, MergeSide, Tree};
+///
+/// let a = Tree::with(1, 'a');
+/// let mut b = Tree::with(1, 'b');
+/// b.insert(2, 'c');
+/// let merged: Vec<_> = merge(&a, &b).collect();
+/// assert_eq!(
+///     merged,
+///     vec![(&1, MergeSide::Both(&'a', &'b')), (&2, MergeSide::Right(&'c'))]
+/// );
+/// ```
+pub fn merge<'a, K: Ord, V>(
+    a: &'a Tree<K, V>,
+    b: &'a Tree<K, V>,
+) -> impl Iterator<Item = (&'a K, MergeSide<&'a V>)> {
+    a.merge_join(b).map(|item| match item {
+        MergeItem::Left(k, v) => (k, MergeSide::Left(v)),
+        MergeItem::Right(k, w) => (k, MergeSide::Right(w)),
+        MergeItem::Both(k, v, w) => (k, MergeSide::Both(v, w)),
+    })
+}
+
+/// Concatenates disjoint trees, given in key order, into one balanced
+/// tree, as produced by [`Tree::split_into`].
+///
+/// This builds the result directly from the parts' sorted entries in
+/// O(n) total. The result uses the first part's strategy.
+///
+/// # Panics
+///
+/// In debug builds, panics if the parts overlap or are out of order.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::{recombine, Tree};
+///
+/// let parts = vec![Tree::with(1, 'a'), Tree::new(), Tree::with(5, 'b')];
+/// let tree = recombine(parts);
+/// assert_eq!(tree.len(), 2);
+/// assert_eq!(tree.get(&5), Some(&'b'));
+/// ```
+pub fn recombine<K: Ord, V>(parts: Vec<Tree<K, V>>) -> Tree<K, V> {
+    debug_assert!(
+        {
+            let mut keys = parts.iter().flat_map(|part| part.iter().map(|(k, _)| k));
+            let mut prev = keys.next();
+            keys.all(|k| prev.replace(k) < Some(k))
+        },
+        "recombined parts must be disjoint and in key order"
+    );
+    let strategy = parts.first().map_or(BalanceStrategy::None, Tree::strategy);
+    let len = parts.iter().map(Tree::len).sum();
+    let mut entries = parts.into_iter().flat_map(IntoIter::new);
+    Tree {
+        root: build_balanced_for(&mut entries, len, strategy),
+        ..Tree::with_strategy(strategy)
+    }
+}
+
+/// A node in a binary search tree
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node<K, V> {
+    /// This node's k
+    k: K,
+    /// This node's v
+    v: V,
+    /// L child
+    l: Option<Box<Self>>,
+    /// R child
+    r: Option<Box<Self>>,
+    /// Number of levels in this subtree, 1 for a leaf
+    height: usize,
+    /// Number of nodes in this subtree
+    size: usize,
+    /// Whether this node is red, under `BalanceStrategy::RedBlack`
+    red: bool,
+    /// Number of black nodes on the longest path down from this node,
+    /// itself included
+    black_height: usize,
+}
+
+impl<K, V> Node<K, V> {
+    pub(crate) fn with_children(k: K, v: V, l: Option<Box<Self>>, r: Option<Box<Self>>) -> Self {
+        let mut node = Self {
+            k,
+            v,
+            l,
+            r,
+            height: 1,
+            size: 1,
+            red: false,
+            black_height: 1,
+        };
+        node.update();
+        node
+    }
+
+    /// Recomputes this node's cached `height`, `size` and
+    /// `black_height` from its children.
+    pub(crate) fn update(&mut self) {
+        self.height = 1 + height(&self.l).max(height(&self.r));
+        self.size = 1 + size(&self.l) + size(&self.r);
+        self.black_height =
+            usize::from(!self.red) + black_height(&self.l).max(black_height(&self.r));
+    }
+
+    /// Sets this node's color and refreshes its cached fields.
+    fn paint(&mut self, red: bool) {
+        self.red = red;
+        self.update();
+    }
+}
+
+impl<K: Ord, V> Node<K, V> {
+    pub(crate) fn new(k: K, v: V) -> Self {
+        Self::with_children(k, v, None, None)
+    }
+
+    pub(crate) fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get_node_mut(k).map(|node| &mut node.v)
+    }
+
+    pub(crate) fn get_node_mut<Q>(&mut self, k: &Q) -> Option<&mut Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let lr = match self.k.borrow().cmp(k) {
+            Ordering::Greater => &mut self.l,
+            Ordering::Equal => return Some(self),
+            Ordering::Less => &mut self.r,
+        };
+        match lr {
+            None => None,
+            Some(node) => node.as_mut().get_node_mut(k),
+        }
+    }
+
+    /// Returns this subtree's height, and the absolute balance factor
+    /// and key of its most unbalanced node.
+    pub(crate) fn most_unbalanced(&self) -> (usize, (usize, &K)) {
+        let (lh, l_best) = self.l.as_ref().map_or((0, None), |node| {
+            let (h, best) = node.most_unbalanced();
+            (h, Some(best))
+        });
+        let (rh, r_best) = self.r.as_ref().map_or((0, None), |node| {
+            let (h, best) = node.most_unbalanced();
+            (h, Some(best))
+        });
+        let own = (lh.abs_diff(rh), &self.k);
+        let mut best = l_best.unwrap_or(own);
+        for candidate in [Some(own), r_best].iter().flatten() {
+            if candidate.0 > best.0 {
+                best = *candidate;
+            }
+        }
+        (1 + lh.max(rh), best)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.size
+    }
+}
+
+/// A sorted iterator over a `Tree`'s key-value pairs.
+///
+/// The iterator borrows the tree, so the tree can't be modified
+/// underneath it:
+///
+/// ```compile_fail
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with(1, 'a');
+/// for (&k, _) in tree.iter() {
+///     tree.insert(k + 1, 'b');
+/// }
+/// ```
+pub struct Iter<'a, K, V>(NodeIter<'a, K, V>);
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn new(tree: &'a Tree<K, V>) -> Self {
+        Iter(NodeIter::new(tree))
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|node| (&node.k, &node.v))
+    }
+}
+
+/// A read-only handle to a node of a `Tree`, created by [`Tree::root`].
+///
+/// It exposes a node's entry and children, so tools such as custom
+/// visualizers can walk the tree's shape.
+#[derive(Debug)]
+pub struct NodeRef<'a, K, V>(&'a Node<K, V>);
+
+impl<'a, K, V> Clone for NodeRef<'a, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for NodeRef<'a, K, V> {}
+
+impl<'a, K, V> NodeRef<'a, K, V> {
+    /// Returns the node's key.
+    pub fn key(&self) -> &'a K {
+        &self.0.k
+    }
+
+    /// Returns the node's value.
+    pub fn value(&self) -> &'a V {
+        &self.0.v
+    }
+
+    /// Returns the node's left child, whose keys are all smaller.
+    pub fn left(&self) -> Option<NodeRef<'a, K, V>> {
+        self.0.l.as_deref().map(NodeRef)
+    }
+
+    /// Returns the node's right child, whose keys are all larger.
+    pub fn right(&self) -> Option<NodeRef<'a, K, V>> {
+        self.0.r.as_deref().map(NodeRef)
+    }
+}
+
+/// A sorted iterator over the entries in a key range, created by
+/// [`Tree::range`].
+pub struct Range<'a, K, V> {
+    iter: NodeIter<'a, K, V>,
+    /// The last node in the range, or `None` once it has been yielded
+    /// or if the range is empty.
+    last: Option<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let last = self.last?;
+        let node = self.iter.next()?;
+        if core::ptr::eq(node, last) {
+            self.last = None;
+        }
+        Some((&node.k, &node.v))
+    }
+}
+
+/// A sorted iterator over a `Tree`'s keys, created by [`Tree::keys`].
+pub struct Keys<'a, K, V>(NodeIter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|node| &node.k)
+    }
+}
+
+/// An iterator over a `Tree`'s values in key order, created by
+/// [`Tree::values`].
+pub struct Values<'a, K, V>(NodeIter<'a, K, V>);
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|node| &node.v)
+    }
+}
+
+pub struct NodeIter<'a, K, V> {
+    curr: Option<&'a Node<K, V>>,
+    stack: Vec<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> NodeIter<'a, K, V> {
+    pub fn new(tree: &'a Tree<K, V>) -> Self {
+        Self {
+            curr: tree.root.as_deref(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn rooted(node: &'a Node<K, V>) -> Self {
+        Self {
+            curr: Some(node),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Starts the iteration at the first key at or above the lower
+    /// `bound`, so the skipped prefix costs O(height).
+    fn from_bound<Q>(tree: &'a Tree<K, V>, bound: Bound<&Q>) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut stack = Vec::new();
+        let mut curr = tree.root.as_deref();
+        while let Some(node) = curr {
+            let in_bound = match bound {
+                Bound::Included(b) => node.k.borrow() >= b,
+                Bound::Excluded(b) => node.k.borrow() > b,
+                Bound::Unbounded => true,
+            };
+            if in_bound {
+                stack.push(node);
+                curr = node.l.as_deref();
+            } else {
+                curr = node.r.as_deref();
+            }
+        }
+        Self { curr: None, stack }
+    }
+}
+
+impl<'a, K, V> Iterator for NodeIter<'a, K, V> {
+    type Item = &'a Node<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(curr) = self.curr {
+            self.stack.push(curr);
+            self.curr = curr.l.as_deref();
+        }
+        if let Some(it) = self.stack.pop() {
+            self.curr = it.r.as_deref();
+            Some(it)
+        } else {
+            None
+        }
+    }
+}
+
+/// A sorted iterator over a `Tree`'s keys and mutable values, created
+/// by [`Tree::iter_mut`].
+pub struct IterMut<'a, K, V>(NodeIterMut<'a, K, V>);
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+/// An iterator over mutable references to a `Tree`'s values in key
+/// order, created by [`Tree::values_mut`].
+pub struct ValuesMut<'a, K, V>(NodeIterMut<'a, K, V>);
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+}
+
+/// A sorted iterator over a `Tree`'s keys and mutable values.
+///
+/// Each node is split into disjoint borrows of its key, value and
+/// children as it is reached, so the value can be handed out while the
+/// right child waits on the stack.
+struct NodeIterMut<'a, K, V> {
+    curr: Option<&'a mut Node<K, V>>,
+    stack: Vec<SplitNode<'a, K, V>>,
+}
+
+/// A node's key, value and right child, borrowed separately.
+type SplitNode<'a, K, V> = (&'a K, &'a mut V, Option<&'a mut Node<K, V>>);
+
+impl<'a, K, V> NodeIterMut<'a, K, V> {
+    fn new(tree: &'a mut Tree<K, V>) -> Self {
+        Self {
+            curr: tree.root.as_deref_mut(),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for NodeIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.curr.take() {
+            let Node { k, v, l, r, .. } = node;
+            self.stack.push((k, v, r.as_deref_mut()));
+            self.curr = l.as_deref_mut();
+        }
+        let (k, v, r) = self.stack.pop()?;
+        self.curr = r;
+        Some((k, v))
+    }
+}
+
+/// Consumes the `Tree`, yielding its owned key-value pairs in sorted
+/// order.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with(2, String::from("two"));
+/// tree.insert(1, String::from("one"));
+/// let mut names = Vec::new();
+/// for (_, name) in tree {
+///     names.push(name);
+/// }
+/// assert_eq!(names, vec!["one", "two"]);
+/// ```
+impl<K, V> IntoIterator for Tree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+
+/// Iterates over the `Tree`'s key-value pairs in sorted order, like
+/// [`Tree::iter`].
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with(2, 'b');
+/// tree.insert(1, 'a');
+/// let mut letters = String::new();
+/// for (_, &c) in &tree {
+///     letters.push(c);
+/// }
+/// assert_eq!(letters, "ab");
+/// ```
+impl<'a, K, V> IntoIterator for &'a Tree<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Builds a `Tree` by inserting each pair in turn, so the last value
+/// for a repeated key wins.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let tree: Tree<_, _> = vec![("a", 1), ("b", 2), ("a", 3)].into_iter().collect();
+/// assert_eq!(tree.len(), 2);
+/// assert_eq!(tree.get("a"), Some(&3));
+/// ```
+impl<K: Ord, V> FromIterator<(K, V)> for Tree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (k, v) in iter {
+            tree.insert(k, v);
+        }
+        tree
+    }
+}
+
+/// Inserts each pair in turn, so the last value for a repeated key
+/// wins.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with(1, 'a');
+/// tree.extend(vec![(3, 'c'), (1, 'z')]);
+/// assert_eq!(tree.get(&1), Some(&'z'));
+/// assert_eq!(tree.len(), 2);
+/// ```
+impl<K: Ord, V> Extend<(K, V)> for Tree<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+/// Copies each borrowed pair in, so another tree's `iter()` can be
+/// folded in directly.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with(1, 'a');
+/// tree.extend(Tree::with(2, 'b').iter());
+/// assert_eq!(tree.get(&2), Some(&'b'));
+/// ```
+impl<'a, K: Ord + Copy, V: Copy> Extend<(&'a K, &'a V)> for Tree<K, V> {
+    fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+/// Returns a reference to the value for the key, like
+/// [`get`](Tree::get).
+///
+/// # Panics
+///
+/// Panics if the key is not in the tree.
+///
+/// # Examples
+///
+/// ```
+/// use binsearchtree::Tree;
+///
+/// let mut tree = Tree::with("cat", "meow");
+/// tree.insert("dog", "woof");
+/// assert_eq!(tree["dog"], "woof");
+/// ```
+impl<K, Q, V> Index<&Q> for Tree<K, V>
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, k: &Q) -> &V {
+        self.get(k).expect("no entry found for key")
+    }
+}
+
+/// A sorted iterator that moves key-value pairs out of a `Tree`,
+/// created by its [`IntoIterator`] implementation.
+pub struct IntoIter<K, V> {
+    curr: Option<Box<Node<K, V>>>,
+    stack: Vec<Box<Node<K, V>>>,
+}
+
+impl<K, V> IntoIter<K, V> {
+    fn new(tree: Tree<K, V>) -> Self {
+        // The stack never holds more than one node per level.
+        let stack = Vec::with_capacity(height(&tree.root));
+        Self {
+            curr: tree.root,
+            stack,
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(mut curr) = self.curr.take() {
+            self.curr = curr.l.take();
+            self.stack.push(curr);
+        }
+        let Node { k, v, r, .. } = *self.stack.pop()?;
+        self.curr = r;
+        Some((k, v))
+    }
+}
+
+/// An in-order iterator over the nodes no deeper than `max_depth`,
+/// yielding each node with its depth.
+struct DepthIter<'a, K, V> {
+    curr: Option<(&'a Node<K, V>, usize)>,
+    stack: Vec<(&'a Node<K, V>, usize)>,
+    max_depth: usize,
+}
+
+impl<'a, K, V> DepthIter<'a, K, V> {
+    fn new(tree: &'a Tree<K, V>, max_depth: usize) -> Self {
+        Self {
+            curr: tree.root.as_deref().map(|node| (node, 0)),
+            stack: Vec::new(),
+            max_depth,
+        }
+    }
+
+    fn child(
+        &self,
+        child: Option<&'a Node<K, V>>,
+        depth: usize,
+    ) -> Option<(&'a Node<K, V>, usize)> {
+        if depth < self.max_depth {
+            child.map(|node| (node, depth + 1))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for DepthIter<'a, K, V> {
+    type Item = (&'a Node<K, V>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((curr, depth)) = self.curr {
+            self.stack.push((curr, depth));
+            self.curr = self.child(curr.l.as_deref(), depth);
+        }
+        let (it, depth) = self.stack.pop()?;
+        self.curr = self.child(it.r.as_deref(), depth);
+        Some((it, depth))
+    }
+}
+
+/// A constant-space, sorted traversal of a `Tree`, created by
+/// [`Tree::stream_in_order`].
+///
+/// Like a Morris traversal, this borrows the tree's own links instead
+/// of keeping a stack. Threading isn't expressible with `Box` links, so
+/// it reverses the child links along its path instead, flagging each
+/// node whose left link was reversed in a spare bit of its cached
+/// height. Every link and height is restored by the time the traversal
+/// finishes or is dropped, and no keys are compared along the way.
+///
+/// Each entry is only borrowed until the following call to
+/// [`next`](MorrisIter::next), so this is a streaming iterator rather
+/// than an [`Iterator`].
+pub struct MorrisIter<'a, K, V> {
+    tree: &'a mut Tree<K, V>,
+    /// The node being visited and its subtree.
+    curr: Option<Box<Node<K, V>>>,
+    /// `curr`'s parent, whose link toward `curr` now points to its own
+    /// parent instead.
+    parents: Option<Box<Node<K, V>>>,
+    state: MorrisState,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum MorrisState {
+    /// `curr`'s left subtree has not been visited.
+    Descend,
+    /// `curr` and its left subtree have been visited.
+    Visited,
+    /// Every node has been visited and the tree restored.
+    Done,
+}
+
+/// Set in the `height` of a node whose left link points to its parent
+/// during a [`MorrisIter`] traversal. Heights never come close to it.
+const REVERSED_LEFT: usize = 1 << (usize::BITS - 1);
+
+impl<'a, K, V> MorrisIter<'a, K, V> {
+    /// Advances the traversal and returns the next entry.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&K, &V)> {
+        loop {
+            match self.state {
+                MorrisState::Descend => {
+                    let mut node = match self.curr.take() {
+                        Some(node) => node,
+                        None => {
+                            self.state = MorrisState::Done;
+                            return None;
+                        }
+                    };
+                    while let Some(l) = node.l.take() {
+                        node.l = self.parents.take();
+                        node.height |= REVERSED_LEFT;
+                        self.parents = Some(node);
+                        node = l;
+                    }
+                    self.curr = Some(node);
+                    self.state = MorrisState::Visited;
+                    break;
+                }
+                MorrisState::Visited => {
+                    let node = self.curr.as_mut().expect("visiting a node");
+                    if let Some(r) = node.r.take() {
+                        node.r = self.parents.take();
+                        self.parents = self.curr.take();
+                        self.curr = Some(r);
+                        self.state = MorrisState::Descend;
+                    } else if !self.ascend() {
+                        self.tree.root = self.curr.take();
+                        self.state = MorrisState::Done;
+                        return None;
+                    } else {
+                        break;
+                    }
+                }
+                MorrisState::Done => return None,
+            }
+        }
+        let node = self.curr.as_deref().expect("visiting a node");
+        Some((&node.k, &node.v))
+    }
+
+    /// Climbs toward the root, restoring links, until it reaches a
+    /// node whose left subtree was just finished. Returns `false` when
+    /// it reaches the root without finding one.
+    fn ascend(&mut self) -> bool {
+        while let Some(mut parent) = self.parents.take() {
+            let child = self.curr.take().expect("climbing from a node");
+            let from_left = parent.height & REVERSED_LEFT != 0;
+            if from_left {
+                parent.height &= !REVERSED_LEFT;
+                self.parents = parent.l.replace(child);
+            } else {
+                self.parents = parent.r.replace(child);
+            }
+            self.curr = Some(parent);
+            if from_left {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<'a, K, V> Drop for MorrisIter<'a, K, V> {
+    fn drop(&mut self) {
+        if self.state != MorrisState::Done {
+            while self.ascend() {}
+            self.tree.root = self.curr.take();
+        }
+    }
+}
+
+pub(crate) fn l<K, V>(root: &Option<Box<Node<K, V>>>) -> Option<&Node<K, V>> {
+    match root {
+        None => None,
+        Some(box_root) => box_root.l.as_ref().map(AsRef::as_ref),
+    }
+}
+pub(crate) fn r<K, V>(root: &Option<Box<Node<K, V>>>) -> Option<&Node<K, V>> {
+    match root {
+        None => None,
+        Some(box_root) => box_root.r.as_ref().map(AsRef::as_ref),
+    }
+}
+pub(crate) fn l_mut<K, V>(root: &mut Option<Box<Node<K, V>>>) -> Option<&mut Node<K, V>> {
+    match root {
+        None => None,
+        Some(box_root) => box_root.l.as_mut().map(Box::as_mut),
+    }
+}
+pub(crate) fn len<K: Ord, V>(root: &Option<Box<Node<K, V>>>) -> Option<usize> {
+    root.as_ref().map(|node| node.len())
+}
+pub(crate) fn r_mut<K, V>(root: &mut Option<Box<Node<K, V>>>) -> Option<&mut Node<K, V>> {
+    match root {
+        None => None,
+        Some(box_root) => box_root.r.as_mut().map(Box::as_mut),
+    }
+}
+pub(crate) fn height<K, V>(root: &Option<Box<Node<K, V>>>) -> usize {
+    root.as_ref().map_or(0, |node| node.height)
+}
+pub(crate) fn size<K, V>(root: &Option<Box<Node<K, V>>>) -> usize {
+    root.as_ref().map_or(0, |node| node.size)
+}
+pub(crate) fn black_height<K, V>(root: &Option<Box<Node<K, V>>>) -> usize {
+    root.as_ref().map_or(0, |node| node.black_height)
+}
+pub(crate) fn is_red<K, V>(root: &Option<Box<Node<K, V>>>) -> bool {
+    root.as_ref().is_some_and(|node| node.red)
+}
+
+/// Inserts `k` and `v` into the subtree at `root`, rebalancing it with
+/// `strategy` on the way back up.
+///
+/// Returns the value previously stored for `k`, if any, the rank of
+/// `k` within the subtree, and the number of rotations performed.
+/// Rotations preserve the rank, so it can be used to find the node
+/// again afterward.
+pub(crate) fn insert<K: Ord, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    k: K,
+    v: V,
+    strategy: BalanceStrategy,
+    probe: &Probe,
+) -> Inserted<V> {
+    let node = match root {
+        None => {
+            let mut leaf = Box::new(Node::new(k, v));
+            if strategy == BalanceStrategy::RedBlack {
+                leaf.paint(true);
+            }
+            *root = Some(leaf);
+            return Inserted {
+                old: None,
+                rank: 0,
+                rotations: 0,
+            };
+        }
+        Some(node) => node,
+    };
+    probe.hit();
+    let mut inserted = match node.k.cmp(&k) {
+        Ordering::Greater => insert(&mut node.l, k, v, strategy, probe),
+        Ordering::Equal => {
+            return Inserted {
+                old: Some(mem::replace(&mut node.v, v)),
+                rank: size(&node.l),
+                rotations: 0,
+            };
+        }
+        Ordering::Less => {
+            let offset = size(&node.l) + 1;
+            let mut inserted = insert(&mut node.r, k, v, strategy, probe);
+            inserted.rank += offset;
+            inserted
+        }
+    };
+    inserted.rotations += rebalance(root, strategy);
+    inserted
+}
+
+/// Links the detached leaf `leaf` into the subtree at `root` so that it
+/// has the given rank, rebalancing on the way back up.
+///
+/// `rank` must be the number of keys in the subtree less than the
+/// leaf's key, which must not already be present. No keys are compared.
+pub(crate) fn insert_at_rank<K, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    rank: usize,
+    leaf: Box<Node<K, V>>,
+    strategy: BalanceStrategy,
+) {
+    let node = match root {
+        None => {
+            let mut leaf = leaf;
+            if strategy == BalanceStrategy::RedBlack {
+                leaf.paint(true);
+            }
+            *root = Some(leaf);
+            return;
+        }
+        Some(node) => node,
+    };
+    let l_size = size(&node.l);
+    if rank <= l_size {
+        insert_at_rank(&mut node.l, rank, leaf, strategy);
+    } else {
+        insert_at_rank(&mut node.r, rank - l_size - 1, leaf, strategy);
+    }
+    rebalance(root, strategy);
+}
+
+/// The outcome of [`insert`].
+pub(crate) struct Inserted<V> {
+    old: Option<V>,
+    rank: usize,
+    rotations: usize,
+}
+
+/// Unlinks the node for `k` from the subtree at `root`, rebalancing on
+/// the way back up, and returns it without children.
+///
+/// A node with two children is replaced by its in-order successor.
+pub(crate) fn remove_node<K, V, Q>(
+    root: &mut Option<Box<Node<K, V>>>,
+    k: &Q,
+    strategy: BalanceStrategy,
+) -> Option<Box<Node<K, V>>>
+where
+    K: Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    let node = root.as_mut()?;
+    let removed = match node.k.borrow().cmp(k) {
+        Ordering::Greater => remove_node(&mut node.l, k, strategy),
+        Ordering::Less => remove_node(&mut node.r, k, strategy),
+        Ordering::Equal => {
+            let mut node = root.take().expect("matched above");
+            *root = match (node.l.take(), node.r.take()) {
+                (None, None) => None,
+                (Some(child), None) | (None, Some(child)) => Some(child),
+                (Some(l), Some(r)) => {
+                    let mut r = Some(r);
+                    let mut successor = take_min(&mut r, strategy);
+                    successor.l = Some(l);
+                    successor.r = r;
+                    successor.red = node.red;
+                    Some(successor)
+                }
+            };
+            Some(node)
+        }
+    };
+    rebalance(root, strategy);
+    removed
+}
+
+/// Detaches and returns the leftmost node of the non-empty subtree at
+/// `root`, rebalancing what remains.
+fn take_min<K, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+) -> Box<Node<K, V>> {
+    let node = root.as_mut().expect("subtree is not empty");
+    if node.l.is_some() {
+        let min = take_min(&mut node.l, strategy);
+        rebalance(root, strategy);
+        min
+    } else {
+        let mut min = root.take().expect("checked above");
+        *root = min.r.take();
+        min
+    }
+}
+
+/// Detaches and returns the rightmost node of the non-empty subtree at
+/// `root`, rebalancing what remains.
+fn take_max<K, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+) -> Box<Node<K, V>> {
+    let node = root.as_mut().expect("subtree is not empty");
+    if node.r.is_some() {
+        let max = take_max(&mut node.r, strategy);
+        rebalance(root, strategy);
+        max
+    } else {
+        let mut max = root.take().expect("checked above");
+        *root = max.l.take();
+        max
+    }
+}
+
+/// Returns the node with the given rank in the subtree at `root`.
+pub(crate) fn select_mut<K, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    mut rank: usize,
+) -> Option<&mut Node<K, V>> {
+    let mut curr = root.as_deref_mut();
+    while let Some(node) = curr {
+        let l_size = size(&node.l);
+        curr = match rank.cmp(&l_size) {
+            Ordering::Less => node.l.as_deref_mut(),
+            Ordering::Equal => return Some(node),
+            Ordering::Greater => {
+                rank -= l_size + 1;
+                node.r.as_deref_mut()
+            }
+        };
+    }
+    None
+}
+
+/// The two subtrees a [`split_rank`] cut produces, lower keys first.
+type Halves<K, V> = (Option<Box<Node<K, V>>>, Option<Box<Node<K, V>>>);
+
+/// Cuts the subtree at `root` into the entries with the `rank` smallest
+/// keys and the rest, each rebalanced with `strategy`.
+///
+/// The cut follows a single root-to-leaf path chosen by subtree size,
+/// and each node on it is joined back onto the side it belongs to, so
+/// no keys are compared.
+fn split_rank<K, V>(
+    root: Option<Box<Node<K, V>>>,
+    rank: usize,
+    strategy: BalanceStrategy,
+) -> Halves<K, V> {
+    let mut node = match root {
+        None => return (None, None),
+        Some(node) => node,
+    };
+    let (l, r) = (node.l.take(), node.r.take());
+    let l_size = size(&l);
+    if rank <= l_size {
+        let (lower, upper) = split_rank(l, rank, strategy);
+        (lower, join(upper, node, r, strategy))
+    } else {
+        let (lower, upper) = split_rank(r, rank - l_size - 1, strategy);
+        (join(l, node, lower, strategy), upper)
+    }
+}
+
+/// Joins the subtrees `l` and `r` under the childless node `mid`, whose
+/// key lies between theirs, rebalancing with `strategy`.
+///
+/// `mid` is linked in where the taller or heavier side's spine first
+/// matches the other side, and the spine is rebalanced on the way back
+/// up, so this takes time proportional to the difference in height.
+fn join<K, V>(
+    l: Option<Box<Node<K, V>>>,
+    mut mid: Box<Node<K, V>>,
+    r: Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+) -> Option<Box<Node<K, V>>> {
+    let mut root = if outweighs(&l, &r, strategy) {
+        let mut l = l.expect("heavier side is not empty");
+        l.r = join(l.r.take(), mid, r, strategy);
+        Some(l)
+    } else if outweighs(&r, &l, strategy) {
+        let mut r = r.expect("heavier side is not empty");
+        r.l = join(l, mid, r.l.take(), strategy);
+        Some(r)
+    } else {
+        mid.l = l;
+        mid.r = r;
+        mid.red = strategy == BalanceStrategy::RedBlack;
+        Some(mid)
+    };
+    rebalance(&mut root, strategy);
+    root
+}
+
+/// Returns `true` if `a` is too tall or heavy to be a sibling of `b`
+/// under `strategy`.
+fn outweighs<K, V>(
+    a: &Option<Box<Node<K, V>>>,
+    b: &Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+) -> bool {
+    match strategy {
+        BalanceStrategy::None => false,
+        BalanceStrategy::Avl => height(a) > height(b) + 1,
+        BalanceStrategy::WeightBalanced => size(a) + 1 > WB_DELTA * (size(b) + 1),
+        BalanceStrategy::RedBlack => black_height(a) > black_height(b),
+    }
+}
+
+/// Left-rotates `count` nodes down the right spine of `root`, every
+/// other one, folding a vine into a shorter, bushier one.
+fn fold_vine<K, V>(root: &mut Option<Box<Node<K, V>>>, count: usize) {
+    let mut slot = root;
+    for _ in 0..count {
+        rotate_l(slot);
+        slot = &mut slot.as_mut().expect("vine is long enough").r;
+    }
+}
+
+/// Recomputes every cached field in the subtree at `root`, bottom up.
+fn refresh_subtree<K, V>(root: &mut Option<Box<Node<K, V>>>) {
+    if let Some(node) = root {
+        refresh_subtree(&mut node.l);
+        refresh_subtree(&mut node.r);
+        node.update();
+    }
+}
+
+/// Adams' weight-balance parameters: sibling weights (sizes plus one)
+/// stay within a factor of `WB_DELTA`, and a rotation is doubled when
+/// the inner grandchild outweighs the outer one by `WB_GAMMA`.
+const WB_DELTA: usize = 3;
+const WB_GAMMA: usize = 2;
+
+/// Returns the height of the subtree at `root` if its cached fields are
+/// correct and it satisfies `strategy`'s balance invariant.
+fn valid_shape<K, V>(root: &Option<Box<Node<K, V>>>, strategy: BalanceStrategy) -> Option<usize> {
+    let node = match root {
+        None => return Some(0),
+        Some(node) => node,
+    };
+    let lh = valid_shape(&node.l, strategy)?;
+    let rh = valid_shape(&node.r, strategy)?;
+    let (lw, rw) = (size(&node.l) + 1, size(&node.r) + 1);
+    let balanced = match strategy {
+        BalanceStrategy::None => true,
+        BalanceStrategy::Avl => lh.abs_diff(rh) <= 1,
+        BalanceStrategy::WeightBalanced => lw <= WB_DELTA * rw && rw <= WB_DELTA * lw,
+        BalanceStrategy::RedBlack => {
+            black_height(&node.l) == black_height(&node.r)
+                && !(node.red && (is_red(&node.l) || is_red(&node.r)))
+        }
+    };
+    let cached = node.height == 1 + lh.max(rh)
+        && node.size == lw + rw - 1
+        && node.black_height
+            == usize::from(!node.red) + black_height(&node.l).max(black_height(&node.r));
+    if balanced && cached {
+        Some(node.height)
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if the keys of the subtree at `root` are in search
+/// tree order and lie strictly between `lo` and `hi`.
+fn keys_ordered<K: Ord, V>(root: &Option<Box<Node<K, V>>>, lo: Option<&K>, hi: Option<&K>) -> bool {
+    match root {
+        None => true,
+        Some(node) => {
+            lo.is_none_or(|lo| *lo < node.k)
+                && hi.is_none_or(|hi| node.k < *hi)
+                && keys_ordered(&node.l, lo, Some(&node.k))
+                && keys_ordered(&node.r, Some(&node.k), hi)
+        }
+    }
+}
+
+/// Refreshes the cached fields of the node at `root` and restores its
+/// `strategy`'s balance invariant, assuming its subtrees satisfy it.
+///
+/// Returns the number of rotations performed.
+pub(crate) fn rebalance<K, V>(
+    root: &mut Option<Box<Node<K, V>>>,
+    strategy: BalanceStrategy,
+) -> usize {
+    let node = match root {
+        None => return 0,
+        Some(node) => node,
+    };
+    node.update();
+    let mut rotations = 0;
+    match strategy {
+        BalanceStrategy::None => (),
+        BalanceStrategy::Avl => {
+            let (lh, rh) = (height(&node.l), height(&node.r));
+            if lh > rh + 1 {
+                if height(&node.l.as_ref().unwrap().l) < height(&node.l.as_ref().unwrap().r) {
+                    rotate_l(&mut node.l);
+                    rotations += 1;
+                }
+                rotate_r(root);
+                rotations += 1;
+            } else if rh > lh + 1 {
+                if height(&node.r.as_ref().unwrap().r) < height(&node.r.as_ref().unwrap().l) {
+                    rotate_r(&mut node.r);
+                    rotations += 1;
+                }
+                rotate_l(root);
+                rotations += 1;
+            }
+        }
+        BalanceStrategy::WeightBalanced => {
+            let (lw, rw) = (size(&node.l) + 1, size(&node.r) + 1);
+            if rw > WB_DELTA * lw {
+                let r = node.r.as_ref().unwrap();
+                if size(&r.l) + 1 >= WB_GAMMA * (size(&r.r) + 1) {
+                    rotate_r(&mut node.r);
+                    rotations += 1;
+                }
+                rotate_l(root);
+                rotations += 1;
+            } else if lw > WB_DELTA * rw {
+                let l = node.l.as_ref().unwrap();
+                if size(&l.r) + 1 >= WB_GAMMA * (size(&l.l) + 1) {
+                    rotate_l(&mut node.l);
+                    rotations += 1;
+                }
+                rotate_r(root);
+                rotations += 1;
+            }
+        }
+        BalanceStrategy::RedBlack => rotations += rebalance_red_black(root),
+    }
+    rotations
+}
+
+/// Restores the red-black invariants at the non-empty `root`, assuming
+/// its subtrees satisfy them and their black heights differ by at most
+/// one.
+///
+/// A black-height deficit is repaired with the classic sibling cases,
+/// and a red node with a red child is painted black. Either may change
+/// the subtree's black height by one, which the parent's own call then
+/// repairs in turn, so a red subtree root is allowed; the tree's own
+/// root is painted black afterward by [`blacken_root`].
+///
+/// Returns the number of rotations performed.
+fn rebalance_red_black<K, V>(root: &mut Option<Box<Node<K, V>>>) -> usize {
+    let mut rotations = 0;
+    let node = root.as_mut().expect("subtree is not empty");
+    let (lb, rb) = (black_height(&node.l), black_height(&node.r));
+    if lb != rb {
+        let short_left = lb < rb;
+        let (short, tall) = if short_left {
+            (&mut node.l, &mut node.r)
+        } else {
+            (&mut node.r, &mut node.l)
+        };
+        let sibling = tall.as_mut().expect("taller side is not empty");
+        let (near, far) = if short_left {
+            (&mut sibling.l, &mut sibling.r)
+        } else {
+            (&mut sibling.r, &mut sibling.l)
+        };
+        if is_red(short) {
+            short.as_mut().expect("red").paint(false);
+        } else if sibling.red {
+            // Lift the red sibling so the short side gets a black one,
+            // then repair the old root beneath it.
+            sibling.red = node.red;
+            node.red = true;
+            rotations += rotate_toward(root, short_left);
+            let top = root.as_mut().expect("rotated above");
+            let old = if short_left { &mut top.l } else { &mut top.r };
+            rotations += rebalance_red_black(old);
+        } else if is_red(near) || is_red(far) {
+            if !is_red(far) {
+                // Turn the red near nephew into the far one.
+                near.as_mut().expect("red").red = false;
+                sibling.red = true;
+                rotations += rotate_toward(tall, !short_left);
+            }
+            let sibling = tall.as_mut().expect("taller side is not empty");
+            let far = if short_left {
+                &mut sibling.r
+            } else {
+                &mut sibling.l
+            };
+            far.as_mut().expect("red").paint(false);
+            sibling.red = node.red;
+            node.red = false;
+            rotations += rotate_toward(root, short_left);
+        } else {
+            sibling.paint(true);
+            node.red = false;
+        }
+    }
+    let top = root.as_mut().expect("subtree is not empty");
+    if top.red && (is_red(&top.l) || is_red(&top.r)) {
+        top.red = false;
+    }
+    top.update();
+    rotations
+}
+
+/// Paints the root of a red-black tree black, as the top of every
+/// mutation does.
+///
+/// Every path gains the same one black node, so the invariants still
+/// hold, and the next repair starts from a black root.
+fn blacken_root<K, V>(root: &mut Option<Box<Node<K, V>>>, strategy: BalanceStrategy) {
+    if let Some(node) = root {
+        if strategy == BalanceStrategy::RedBlack && node.red {
+            node.paint(false);
+        }
+    }
+}
+
+/// Rotates the subtree at `root` so its root moves down toward the
+/// left if `left`, otherwise toward the right, returning 1.
+fn rotate_toward<K, V>(root: &mut Option<Box<Node<K, V>>>, left: bool) -> usize {
+    if left {
+        rotate_l(root);
+    } else {
+        rotate_r(root);
+    }
+    1
+}
+
+/// Sorts `entries` by key, keeping only the last of any entries that
+/// share a key.
+fn sort_keep_last<K: Ord, V>(entries: &mut Vec<(K, V)>) {
+    // A stable sort keeps colliding keys in their old order, so after
+    // reversing, deduplication keeps the last of each run.
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries.reverse();
+    entries.dedup_by(|a, b| a.0 == b.0);
+    entries.reverse();
+}
+
+/// Builds a balanced subtree from the next `len` sorted entries,
+/// colored to satisfy `strategy`.
+pub(crate) fn build_balanced_for<K, V, I>(
+    entries: &mut I,
+    len: usize,
+    strategy: BalanceStrategy,
+) -> Option<Box<Node<K, V>>>
+where
+    I: Iterator<Item = (K, V)>,
+{
+    let mut root = build_balanced(entries, len);
+    if strategy == BalanceStrategy::RedBlack {
+        // Only the deepest level can be partly filled, so making it red
+        // and everything above it black leaves every path with the same
+        // number of black nodes.
+        let depth = height(&root).saturating_sub(1);
+        paint_level_red(&mut root, depth);
+        blacken_root(&mut root, strategy);
+    }
+    root
+}
+
+/// Paints the nodes `depth` levels below `root` red and all others
+/// black.
+fn paint_level_red<K, V>(root: &mut Option<Box<Node<K, V>>>, depth: usize) {
+    if let Some(node) = root {
+        paint_level_red(&mut node.l, depth.wrapping_sub(1));
+        paint_level_red(&mut node.r, depth.wrapping_sub(1));
+        node.paint(depth == 0);
+    }
+}
+
+/// Builds a balanced subtree from the next `len` sorted entries.
+pub(crate) fn build_balanced<K, V, I>(entries: &mut I, len: usize) -> Option<Box<Node<K, V>>>
+where
+    I: Iterator<Item = (K, V)>,
+{
+    if len == 0 {
+        return None;
+    }
+    let l_len = len / 2;
+    let l = build_balanced(entries, l_len);
+    let (k, v) = entries.next()?;
+    let r = build_balanced(entries, len - l_len - 1);
+    Some(Box::new(Node::with_children(k, v, l, r)))
+}
+pub(crate) fn rotate_r<K, V>(root: &mut Option<Box<Node<K, V>>>) {
+    *root = match root.take() {
+        None => {
+            // Cannot rotate an empty tree
+            return;
+        }
+        Some(mut root) => {
+            match root.l.take() {
+                None => {
+                    // Cannot right rorate if `root` doesn't have left child
+                    Some(root)
+                }
+                Some(mut pivot) => {
+                    root.l = pivot.r.take();
+                    root.update();
+                    pivot.r = Some(root);
+                    pivot.update();
+                    Some(pivot)
+                }
+            }
+        }
+    };
+}
+
+pub(crate) fn rotate_l<K, V>(root: &mut Option<Box<Node<K, V>>>) {
+    *root = match root.take() {
+        None => {
+            // Cannot rotate an empty tree
+            return;
+        }
+        Some(mut root) => {
+            match root.r.take() {
+                None => {
+                    // Cannot left rorate if `root` doesn't have right child
+                    Some(root)
+                }
+                Some(mut pivot) => {
+                    root.r = pivot.l.take();
+                    root.update();
+                    pivot.l = Some(root);
+                    pivot.update();
+                    Some(pivot)
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "graphviz")]
+impl<'a, K, V> dot::Labeller<'a, (K, V), (K, K)> for Tree<K, V>
+where
+    K: ::std::fmt::Display + Copy,
+    V: ::std::fmt::Display + Copy,
+{
+    fn graph_id(&'a self) -> dot::Id<'a> {
+        dot::Id::new("Tree").unwrap()
+    }
+
+    fn node_id(&'a self, n: &(K, V)) -> dot::Id<'a> {
+        dot::Id::new(format!("k_{}", n.0)).unwrap()
+    }
+
+    fn node_label(&'a self, n: &(K, V)) -> dot::LabelText<'a> {
+        dot::LabelText::LabelStr(::std::borrow::Cow::Owned(format!("{}", n.0)))
+    }
+}
+
+#[cfg(feature = "graphviz")]
+impl<'a, K, V> dot::GraphWalk<'a, (K, V), (K, K)> for Tree<K, V>
+where
+    K: ::std::fmt::Display + Copy + Ord,
+    V: ::std::fmt::Display + Copy,
+{
+    fn nodes(&'a self) -> dot::Nodes<'a, (K, V)> {
+        ::std::borrow::Cow::Owned(self.nodes().map(|&Node { k, v, .. }| (k, v)).collect())
+    }
+
+    fn edges(&'a self) -> dot::Edges<'a, (K, K)> {
+        let mut edges: Vec<(K, K)> = Vec::new();
+        for Node { k, l, r, .. } in self.nodes() {
+            if let Some(l) = l.as_deref() {
+                edges.push((*k, l.k));
+            }
+            if let Some(r) = r.as_deref() {
+                edges.push((*k, r.k));
+            }
+        }
+        ::std::borrow::Cow::Owned(edges)
+    }
+
+    fn source(&'a self, e: &(K, K)) -> (K, V) {
+        (e.0, *self.get(&e.0).unwrap())
+    }
+
+    fn target(&'a self, e: &(K, K)) -> (K, V) {
+        (e.1, *self.get(&e.1).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(feature = "std"))]
+    use alloc::{string::String, vec, vec::Vec};
+
+    /// Counts heap allocations made by the current thread.
+    #[cfg(feature = "std")]
+    mod alloc_count {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+
+        struct Counting;
+
+        thread_local! {
+            static COUNT: Cell<usize> = const { Cell::new(0) };
+        }
+
+        unsafe impl GlobalAlloc for Counting {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                COUNT.with(|c| c.set(c.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOCATOR: Counting = Counting;
+
+        /// Returns how many allocations this thread has made so far.
+        pub fn get() -> usize {
+            COUNT.with(Cell::get)
+        }
+    }
+
+    /// Asserts that the subtree at `root` has correct cached fields and
+    /// satisfies `strategy`'s balance invariant, returning its height.
+    fn check_subtree<K, V>(root: &Option<Box<Node<K, V>>>, strategy: BalanceStrategy) -> usize {
+        let node = match root {
+            None => return 0,
+            Some(node) => node,
+        };
+        let lh = check_subtree(&node.l, strategy);
+        let rh = check_subtree(&node.r, strategy);
+        assert_eq!(node.height, 1 + lh.max(rh));
+        assert_eq!(node.size, 1 + size(&node.l) + size(&node.r));
+        assert_eq!(
+            node.black_height,
+            usize::from(!node.red) + black_height(&node.l).max(black_height(&node.r))
+        );
+        match strategy {
+            BalanceStrategy::None => (),
+            BalanceStrategy::Avl => assert!(lh.abs_diff(rh) <= 1),
+            BalanceStrategy::WeightBalanced => {
+                let (lw, rw) = (size(&node.l) + 1, size(&node.r) + 1);
+                assert!(lw <= WB_DELTA * rw && rw <= WB_DELTA * lw);
+            }
+            BalanceStrategy::RedBlack => {
+                assert_eq!(black_height(&node.l), black_height(&node.r));
+                assert!(!(node.red && (is_red(&node.l) || is_red(&node.r))));
+            }
+        }
+        node.height
+    }
+
+    fn check_tree<K: Ord, V>(tree: &Tree<K, V>) {
+        check_subtree(&tree.root, tree.strategy);
+        assert!(tree.validate());
+        let keys: Vec<&K> = tree.iter().map(|(k, _)| k).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn tree_eq_pass() {
+        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
+        let tree_b = Tree::with(String::from("cat"), String::from("meow"));
+        assert_eq!(tree_a, tree_b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tree_eq_fail() {
+        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
+        let tree_b = Tree::with(String::from("dog"), String::from("bark"));
+        assert_eq!(tree_a, tree_b);
+    }
+
+    #[test]
+    fn tree_neq_pass() {
+        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
+        let tree_b = Tree::with(String::from("dog"), String::from("bark"));
+        assert_ne!(tree_a, tree_b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tree_neq_fail() {
+        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
+        let tree_b = Tree::with(String::from("cat"), String::from("meow"));
+        assert_ne!(tree_a, tree_b);
+    }
+
+    #[test]
+    fn tree_insert_pass() {
+        let mut tree_root = Tree::with(1, '1');
+        tree_root.insert(0, '0');
+        tree_root.insert(2, '2');
+        let tree_root_1 = Tree::from_root(Some(Box::new(Node::with_children(
+            1,
+            '1',
+            Some(Box::new(Node::new(0, '0'))),
+            Some(Box::new(Node::new(2, '2'))),
+        ))));
+        assert_eq!(tree_root.root, tree_root_1.root);
+        assert_eq!(tree_root.len(), 3);
+    }
+
+    #[test]
+    fn tree_insert_duplicate_pass() {
+        let mut tree_root = Tree::with(0, '0');
+        assert_eq!(tree_root.insert(1, '1'), None);
+        assert_eq!(tree_root.insert(1, '1'), Some('1'));
+    }
+
+    #[test]
+    fn tree_test_get_pass() {
+        let mut tree_root = Tree::with(1, '1');
+        tree_root.insert(0, '0');
+        tree_root.insert(2, '2');
+        assert_eq!(tree_root.get(&0), Some(&'0'));
+        assert_eq!(tree_root.get(&1), Some(&'1'));
+        assert_eq!(tree_root.get(&2), Some(&'2'));
+    }
+
+    #[test]
+    fn tree_test_iter_pass() {
+        let mut tree: Tree<u8, ()> = Tree::new();
+        for _ in 0..100 {
+            tree.insert(rand::random(), ());
+        }
+        let mut iter = tree.iter();
+        let mut last = *iter.next().unwrap().0;
+        for (&k, _) in iter {
+            assert!(k > last);
+            last = k;
+        }
+    }
+
+    #[test]
+    fn node_rotate_r_pass() {
+        let mut tree = Tree::with(5, 5);
+        tree.insert(7, 7);
+        tree.insert(3, 3);
+        tree.insert(2, 2);
+        tree.insert(4, 4);
+        rotate_r(&mut tree.root);
+    }
+
+    #[test]
+    fn node_rotate_l_pass() {
+        let mut tree = Tree::with(3, 3);
+        tree.insert(2, 2);
+        tree.insert(5, 5);
+        tree.insert(4, 4);
+        tree.insert(7, 7);
+        rotate_l(&mut tree.root);
+    }
+
+    #[test]
+    fn node_rotate_roundtrip_pass() {
+        let mut tree = Tree::with(3, 3);
+        tree.insert(2, 2);
+        tree.insert(5, 5);
+        tree.insert(4, 4);
+        tree.insert(7, 7);
+        let tree_0 = tree.clone();
+        rotate_l(&mut tree.root);
+        let tree_1 = tree.clone();
+        rotate_r(&mut tree.root);
+        let tree_2 = tree;
+
+        assert_ne!(tree_0.root, tree_1.root);
+        assert_ne!(tree_1.root, tree_2.root);
+        assert_eq!(tree_0.root, tree_2.root);
+    }
+
+    #[test]
+    fn tree_invert_pass() {
+        let mut tree = Tree::with(2, 'b');
+        tree.insert(1, 'a');
+        tree.insert(3, 'c');
+        let inverted = tree.invert();
+        assert_eq!(inverted.len(), 3);
+        assert_eq!(inverted.get(&'a'), Some(&1));
+        assert_eq!(inverted.get(&'b'), Some(&2));
+        assert_eq!(inverted.get(&'c'), Some(&3));
+
+        // Values rising with their keys must not build a chain.
+        let mut letters = Tree::with_strategy(BalanceStrategy::RedBlack);
+        for (k, v) in (0..26).zip('a'..='z') {
+            letters.insert(k, v);
+        }
+        let inverted = letters.invert();
+        assert_eq!(inverted.strategy(), BalanceStrategy::RedBlack);
+        assert_eq!(inverted.height(), 4);
+        assert!(inverted
+            .iter()
+            .map(|(&v, &k)| (k, v))
+            .eq((0..26).zip('a'..='z')));
+        check_tree(&inverted);
+    }
+
+    #[test]
+    fn tree_invert_duplicate_pass() {
+        let mut tree = Tree::with(1, 'x');
+        tree.insert(2, 'x');
+        let inverted = tree.invert();
+        assert_eq!(inverted.len(), 1);
+        assert_eq!(inverted.get(&'x'), Some(&2));
+    }
+
+    #[test]
+    fn tree_range_bounds_entries_pass() {
+        let mut tree = Tree::new();
+        for &k in &[50, 20, 80, 10, 30, 70, 90] {
+            tree.insert(k, k + 1);
+        }
+        assert_eq!(
+            tree.range_bounds_entries(20..80),
+            (Some((&20, &21)), Some((&70, &71)))
+        );
+        assert_eq!(
+            tree.range_bounds_entries(20..=80),
+            (Some((&20, &21)), Some((&80, &81)))
+        );
+        assert_eq!(
+            tree.range_bounds_entries(25..75),
+            (Some((&30, &31)), Some((&70, &71)))
+        );
+        assert_eq!(
+            tree.range_bounds_entries(..),
+            (Some((&10, &11)), Some((&90, &91)))
+        );
+        assert_eq!(
+            tree.range_bounds_entries(..=10),
+            (Some((&10, &11)), Some((&10, &11)))
+        );
+        assert_eq!(
+            tree.range_bounds_entries((Bound::Excluded(10), Bound::Excluded(30))),
+            (Some((&20, &21)), Some((&20, &21)))
+        );
+    }
+
+    #[test]
+    fn tree_range_bounds_entries_empty_pass() {
+        let mut tree = Tree::new();
+        for &k in &[50, 20, 80] {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.range_bounds_entries(21..50), (None, None));
+        assert_eq!(tree.range_bounds_entries(81..), (None, None));
+        assert_eq!(tree.range_bounds_entries(..20), (None, None));
+        let empty: Tree<i32, ()> = Tree::new();
+        assert_eq!(empty.range_bounds_entries(..), (None, None));
+    }
+
+    #[test]
+    fn tree_entries_mut_pass() {
+        let mut tree = Tree::with(2, 20);
+        tree.insert(1, 10);
+        {
+            let mut vals = tree.entries_mut(&[3, 1, 2]);
+            assert_eq!(vals.len(), 3);
+            *vals[0] += 3;
+            *vals[1] += 1;
+            *vals[2] += 2;
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(&1), Some(&11));
+        assert_eq!(tree.get(&2), Some(&22));
+        assert_eq!(tree.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn tree_entries_mut_ancestor_first_pass() {
+        // Unbalanced, so each key is an ancestor of every later one.
+        let mut tree = Tree::new();
+        for k in 0..5 {
+            tree.insert(k, k * 10);
+        }
+        {
+            let mut vals = tree.entries_mut(&[0, 4, 2]);
+            *vals[0] += 1;
+            *vals[1] += 4;
+            *vals[2] += 2;
+        }
+        let values: Vec<i32> = tree.values().copied().collect();
+        assert_eq!(values, vec![1, 10, 22, 30, 44]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tree_entries_mut_duplicate_fail() {
+        let mut tree = Tree::with(1, 10);
+        tree.entries_mut(&[1, 2, 1]);
+    }
+
+    #[test]
+    fn tree_depth_histogram_pass() {
+        let mut tree = Tree::new();
+        for &k in &[4, 2, 6, 1, 3, 5, 7] {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.depth_histogram(), vec![1, 2, 4]);
+
+        let mut skewed = Tree::new();
+        for k in 0..4 {
+            skewed.insert(k, ());
+        }
+        assert_eq!(skewed.depth_histogram(), vec![1, 1, 1, 1]);
+        assert!(Tree::<i32, ()>::new().depth_histogram().is_empty());
+    }
+
+    #[test]
+    fn tree_most_unbalanced_key_pass() {
+        let mut tree = Tree::new();
+        for k in 0..8 {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.most_unbalanced_key(), Some(&0));
+
+        let mut tree = Tree::with(4, ());
+        for &k in &[2, 6, 1, 3, 7, 8, 9] {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.most_unbalanced_key(), Some(&6));
+        assert_eq!(Tree::<i32, ()>::new().most_unbalanced_key(), None);
+    }
+
+    #[test]
+    fn tree_iter_cloned_pass() {
+        let mut tree = Tree::with(String::from("dog"), String::from("woof"));
+        tree.insert(String::from("cat"), String::from("meow"));
+        let pairs: Vec<(String, String)> = tree.iter_cloned().collect();
+        assert_eq!(pairs[0], (String::from("cat"), String::from("meow")));
+        assert_eq!(pairs[1], (String::from("dog"), String::from("woof")));
+        tree.insert(String::from("cow"), String::from("moo"));
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get("cat"), Some(&String::from("meow")));
+    }
+
+    #[test]
+    fn tree_distinct_value_count_pass() {
+        let mut tree = Tree::new();
+        for k in 0..20 {
+            tree.insert(k, k % 7);
+        }
+        assert_eq!(tree.distinct_value_count(), 7);
+        assert_eq!(Tree::<i32, i32>::new().distinct_value_count(), 0);
+    }
+
+    #[test]
+    fn tree_get_sorted_pass() {
+        let mut tree = Tree::new();
+        for _ in 0..100 {
+            let k: u8 = rand::random();
+            tree.insert(k, u16::from(k) * 2);
+        }
+        let queries: Vec<u8> = (0..=255).step_by(3).chain(core::iter::once(255)).collect();
+        let refs: Vec<&u8> = queries.iter().collect();
+        let expected: Vec<Option<&u16>> = queries.iter().map(|q| tree.get(q)).collect();
+        assert_eq!(tree.get_sorted(&refs), expected);
+        assert_eq!(tree.get_sorted::<u8>(&[]), vec![]);
+    }
+
+    #[test]
+    fn tree_entry_or_insert_reporting_pass() {
+        let mut tree = Tree::with(1, 10);
+        let (v, existed) = tree.entry_or_insert_reporting(2, 20);
+        assert!(!existed);
+        assert_eq!(*v, 20);
+        let (v, existed) = tree.entry_or_insert_reporting(1, 0);
+        assert!(existed);
+        *v += 1;
+        assert_eq!(tree.get(&1), Some(&11));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn tree_to_btreemap_pass() {
+        let mut tree = Tree::new();
+        for _ in 0..100 {
+            let k: u8 = rand::random();
+            tree.insert(k, u32::from(k) + 1);
+        }
+        let map = tree.to_btreemap();
+        assert_eq!(map.len(), tree.len());
+        assert!(map.iter().eq(tree.iter()));
+    }
+
+    #[test]
+    fn tree_retain_top_n_by_value_pass() {
+        let mut tree = Tree::new();
+        for (k, &v) in [5, 9, 1, 7, 3, 8, 2, 6, 4, 0].iter().enumerate() {
+            tree.insert(k, v);
+        }
+        tree.retain_top_n_by_value(3);
+        let kept: Vec<(usize, i32)> = tree.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(kept, vec![(1, 9), (3, 7), (5, 8)]);
+        assert_eq!(tree.depth_histogram(), vec![1, 2]);
+    }
+
+    #[test]
+    fn tree_retain_top_n_by_value_ties_pass() {
+        let mut tree = Tree::new();
+        for k in 0..10 {
+            tree.insert(k, k / 4);
+        }
+        tree.retain_top_n_by_value(3);
+        let kept: Vec<i32> = tree.iter().map(|(&k, _)| k).collect();
+        assert_eq!(kept, vec![4, 8, 9]);
+    }
+
+    #[test]
+    fn tree_increment_pass() {
+        let mut tree: Tree<&str, u64> = Tree::new();
+        for word in "the cat and the dog and the cow".split(' ') {
+            tree.increment(word);
+        }
+        assert_eq!(tree.get("the"), Some(&3));
+        assert_eq!(tree.get("and"), Some(&2));
+        assert_eq!(tree.get("cow"), Some(&1));
+        assert_eq!(tree.len(), 5);
+    }
+
+    #[test]
+    fn tree_push_into_pass() {
+        let mut tree = Tree::new();
+        for n in 0..10 {
+            push_into(&mut tree, n % 3, n);
+        }
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(&0), Some(&vec![0, 3, 6, 9]));
+        assert_eq!(tree.get(&1), Some(&vec![1, 4, 7]));
+        assert_eq!(tree.get(&2), Some(&vec![2, 5, 8]));
+    }
+
+    #[test]
+    fn tree_iter_to_depth_pass() {
+        let mut tree = Tree::new();
+        for &k in &[4, 2, 6, 1, 3, 5, 7] {
+            tree.insert(k, ());
+        }
+        let top: Vec<(i32, usize)> = tree.iter_to_depth(1).map(|(&k, _, d)| (k, d)).collect();
+        assert_eq!(top, vec![(2, 1), (4, 0), (6, 1)]);
+        assert_eq!(tree.iter_to_depth(2).count(), 7);
+        assert_eq!(tree.iter_to_depth(0).count(), 1);
+    }
+
+    #[test]
+    fn tree_height_pass() {
+        assert_eq!(Tree::<i32, ()>::new().height(), 0);
+        let mut tree = Tree::new();
+        for &k in &[4, 2, 6, 1, 3, 5, 7] {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.height(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_rebuild_if_unbalanced_pass() {
+        let mut skewed = Tree::new();
+        for k in 0..100 {
+            skewed.insert(k, k);
+        }
+        assert!(skewed.rebuild_if_unbalanced(2.0));
+        assert_eq!(skewed.height(), 6);
+        assert_eq!(skewed.len(), 100);
+        assert!(skewed.iter().map(|(&k, _)| k).eq(0..100));
+
+        assert!(!skewed.rebuild_if_unbalanced(2.0));
+        assert!(!Tree::with(1, 1).rebuild_if_unbalanced(0.0));
+    }
+
+    #[test]
+    fn tree_preorder_bytes_roundtrip_pass() {
+        for &strategy in &BalanceStrategy::ALL {
+            assert_eq!(
+                BalanceStrategy::from_byte(strategy.to_byte()),
+                Some(strategy)
+            );
+            let mut tree: Tree<u32, u32> = Tree::with_strategy(strategy);
+            for _ in 0..100 {
+                let k: u32 = rand::random();
+                tree.insert(k, k.wrapping_mul(3));
+            }
+            let bytes = tree.to_preorder_bytes();
+            assert_eq!(bytes.len(), 1 + tree.len() * 9 + tree.len() + 1);
+            let mut decoded = Tree::from_preorder_bytes(&bytes).unwrap();
+            assert_eq!(decoded.strategy(), strategy);
+            assert_eq!(decoded.root, tree.root);
+            #[cfg(feature = "std")]
+            assert_eq!(decoded.structural_hash(), tree.structural_hash());
+            assert_eq!(decoded.depth_histogram(), tree.depth_histogram());
+
+            // The decoded tree keeps balancing like the original.
+            for k in 0..200 {
+                decoded.insert(k, k);
+                tree.insert(k, k);
+            }
+            assert_eq!(decoded.root, tree.root);
+            check_tree(&decoded);
+        }
+    }
+
+    #[test]
+    fn tree_preorder_bytes_invalid_pass() {
+        let tree: Tree<u8, u8> = Tree::with(2, 0);
+        let mut bytes = tree.to_preorder_bytes();
+        assert_eq!(bytes, vec![0, 1, 2, 0, 0, 0]);
+        bytes.push(0);
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&bytes), None);
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[]), None);
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[0]), None);
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[0, 2]), None);
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&[4, 0]), None);
+        // Root 2 with left child 3 is not a search tree.
+        let unordered = [0, 1, 2, 0, 1, 3, 0, 0, 0, 0];
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&unordered), None);
+        // A chain of three is a fine plain tree but not an AVL one.
+        let mut chain = vec![0, 1, 1, 0, 0, 1, 2, 0, 0, 1, 3, 0, 0, 0];
+        assert!(Tree::<u8, u8>::from_preorder_bytes(&chain).is_some());
+        chain[0] = BalanceStrategy::Avl.to_byte();
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&chain), None);
+        // Red nodes need the red-black strategy, and must not be stacked.
+        let red_pair = [3, 1, 3, 0, 2, 2, 0, 2, 1, 0, 0, 0, 0, 0];
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&red_pair), None);
+        let balanced = [3, 1, 2, 0, 2, 1, 0, 0, 0, 2, 3, 0, 0, 0];
+        assert!(Tree::<u8, u8>::from_preorder_bytes(&balanced).is_some());
+        let uncolored = [0, 1, 2, 0, 2, 1, 0, 0, 0, 2, 3, 0, 0, 0];
+        assert_eq!(Tree::<u8, u8>::from_preorder_bytes(&uncolored), None);
+    }
+
+    #[test]
+    fn tree_lowest_common_ancestor_pass() {
+        let mut tree = Tree::new();
+        for &k in &[4, 2, 6, 1, 3, 5, 7] {
+            tree.insert(k, ());
+        }
+        assert_eq!(tree.lowest_common_ancestor(&1, &3), Some(&2));
+        assert_eq!(tree.lowest_common_ancestor(&3, &1), Some(&2));
+        assert_eq!(tree.lowest_common_ancestor(&1, &7), Some(&4));
+        assert_eq!(tree.lowest_common_ancestor(&5, &7), Some(&6));
+        assert_eq!(tree.lowest_common_ancestor(&2, &3), Some(&2));
+        assert_eq!(tree.lowest_common_ancestor(&4, &5), Some(&4));
+        assert_eq!(tree.lowest_common_ancestor(&3, &3), Some(&3));
+        assert_eq!(tree.lowest_common_ancestor(&0, &3), None);
+        assert_eq!(tree.lowest_common_ancestor(&3, &8), None);
+    }
+
+    #[test]
+    fn tree_path_between_pass() {
+        let mut tree = Tree::new();
+        for &k in &[4, 2, 6, 1, 3, 5, 7] {
+            tree.insert(k, ());
+        }
+        let path = tree.path_between(&3, &7).unwrap();
+        assert_eq!(path, vec![&3, &2, &4, &6, &7]);
+        assert_eq!(path.len() - 1, 4);
+        assert_eq!(tree.path_between(&7, &3), Some(vec![&7, &6, &4, &2, &3]));
+        assert_eq!(tree.path_between(&2, &1), Some(vec![&2, &1]));
+        assert_eq!(tree.path_between(&1, &4), Some(vec![&1, &2, &4]));
+        assert_eq!(tree.path_between(&5, &5), Some(vec![&5]));
+        assert_eq!(tree.path_between(&5, &8), None);
+    }
+
+    #[test]
+    fn tree_zst_values_pass() {
+        let mut tree: Tree<i32, ()> = Tree::new();
+        for k in 0..10 {
+            assert_eq!(tree.entry_or_insert_reporting(k, ()), (&mut (), false));
+        }
+        assert_eq!(tree.entry_or_insert_reporting(3, ()), (&mut (), true));
+        {
+            let vals = tree.entries_mut(&[9, 10, 0]);
+            assert_eq!(vals.len(), 3);
+            for v in vals {
+                *v = ();
+            }
+        }
+        assert_eq!(tree.len(), 11);
+        assert_eq!(tree.get(&10), Some(&()));
+        assert!(tree.iter().map(|(&k, _)| k).eq(0..11));
+
+        // Every `&mut ()` has the same address, so these must still
+        // hand out exactly one reference per key.
+        assert_eq!(tree.entry(11).or_insert(()), &mut ());
+        assert_eq!(tree.entry(5).or_insert(()), &mut ());
+        assert_eq!(tree.len(), 12);
+        assert_eq!(tree.get_mut(&7), Some(&mut ()));
+        assert_eq!(tree.get_mut(&12), None);
+        let mut seen = Vec::new();
+        for (&k, v) in tree.iter_mut() {
+            *v = ();
+            seen.push(k);
+        }
+        assert_eq!(seen, (0..12).collect::<Vec<_>>());
+        assert_eq!(tree.iter_mut().count(), 12);
+        check_tree(&tree);
+    }
+
+    #[test]
+    fn tree_count_values_in_pass() {
+        let mut tree = Tree::new();
+        for k in 0..30 {
+            tree.insert(k, 30 - k);
+        }
+        assert_eq!(tree.count_values_in(10..=20), 11);
+        assert_eq!(tree.count_values_in(..), 30);
+        assert_eq!(tree.count_values_in(31..), 0);
+    }
+
+    #[test]
+    fn tree_into_iter_filter_pass() {
+        use core::cell::Cell;
+
+        struct Counted<'a>(&'a Cell<usize>);
+
+        impl<'a> Drop for Counted<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+        let mut tree = Tree::new();
+        for k in 0..10 {
+            tree.insert(k, Counted(&drops));
+        }
+        let kept: Vec<(i32, Counted)> = tree.into_iter_filter(|k, _| k % 3 == 0).collect();
+        let keys: Vec<i32> = kept.iter().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![0, 3, 6, 9]);
+        assert_eq!(drops.get(), 6);
+        drop(kept);
+        assert_eq!(drops.get(), 10);
+    }
+
+    #[test]
+    fn tree_subtree_iter_pass() {
+        let mut tree = Tree::new();
+        for &k in &[8, 4, 12, 2, 6, 10, 14, 1, 3, 5, 7] {
+            tree.insert(k, k * 2);
+        }
+        let keys: Vec<i32> = tree.subtree_iter(&4).unwrap().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5, 6, 7]);
+        let pairs: Vec<(i32, i32)> = tree
+            .subtree_iter(&12)
+            .unwrap()
+            .map(|(&k, &v)| (k, v))
+            .collect();
+        assert_eq!(pairs, vec![(10, 20), (12, 24), (14, 28)]);
+        assert_eq!(tree.subtree_iter(&7).unwrap().count(), 1);
+        assert_eq!(tree.subtree_iter(&8).unwrap().count(), tree.len());
+        assert!(tree.subtree_iter(&9).is_none());
+    }
+
+    #[test]
+    fn merge_pass() {
+        let mut a = Tree::new();
+        let mut b = Tree::new();
+        for k in 0..6 {
+            a.insert(k, k);
+            b.insert(k + 3, -k);
+        }
+        let merged: Vec<(i32, MergeSide<i32>)> = merge(&a, &b)
+            .map(|(&k, side)| {
+                let side = match side {
+                    MergeSide::Left(&v) => MergeSide::Left(v),
+                    MergeSide::Right(&v) => MergeSide::Right(v),
+                    MergeSide::Both(&va, &vb) => MergeSide::Both(va, vb),
+                };
+                (k, side)
+            })
+            .collect();
+        assert_eq!(merged.len(), 9);
+        assert_eq!(merged[0], (0, MergeSide::Left(0)));
+        assert_eq!(merged[2], (2, MergeSide::Left(2)));
+        assert_eq!(merged[3], (3, MergeSide::Both(3, 0)));
+        assert_eq!(merged[5], (5, MergeSide::Both(5, -2)));
+        assert_eq!(merged[6], (6, MergeSide::Right(-3)));
+        assert_eq!(merged[8], (8, MergeSide::Right(-5)));
+        assert!(merged.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let empty = Tree::new();
+        assert_eq!(merge(&a, &empty).count(), 6);
+        assert_eq!(merge(&empty, &empty).count(), 0);
+    }
+
+    #[test]
+    fn tree_checkpoint_restore_pass() {
+        let mut tree = Tree::new();
+        for k in 0..10 {
+            tree.insert(k, k);
+        }
+        let original = tree.clone();
+        let cp = tree.checkpoint();
+        tree.insert(10, 10);
+        tree.insert(3, 30);
+        tree.retain_top_n_by_value(2);
+        assert_ne!(tree, original);
+        tree.restore(cp);
+        assert_eq!(tree, original);
+    }
+
+    #[test]
+    fn tree_entry_ref_pass() {
+        let mut tree = Tree::with(String::from("cat"), 1);
+        let arg = String::from("cat");
+        let arg_ptr = arg.as_ptr();
+        let (k, v) = tree.entry_ref(arg).or_insert(0);
+        *v += 1;
+        let k_ptr = k.as_ptr();
+        assert_ne!(k_ptr, arg_ptr);
+        assert_eq!(tree.iter().next().unwrap().0.as_ptr(), k_ptr);
+        assert_eq!(tree.get("cat"), Some(&2));
+
+        let (k, v) = tree.entry_ref(String::from("dog")).or_insert_with(|| 7);
+        assert_eq!((k.as_str(), *v), ("dog", 7));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn tree_strategy_sorted_insert_pass() {
+        let mut plain = Tree::new();
+        let mut avl = Tree::with_strategy(BalanceStrategy::Avl);
+        let mut wb = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for k in 0..1000 {
+            plain.insert(k, k);
+            avl.insert(k, k);
+            wb.insert(k, k);
+        }
+        for tree in &[&plain, &avl, &wb] {
+            check_tree(tree);
+            assert_eq!(tree.len(), 1000);
+            assert!(tree.iter().map(|(&k, _)| k).eq(0..1000));
+        }
+        // log2(1000) is just under 10.
+        assert_eq!(plain.height(), 999);
+        assert!(avl.height() as f64 <= 1.44 * 10.0);
+        assert!(wb.height() <= 20);
+    }
+
+    #[test]
+    fn tree_strategy_random_insert_pass() {
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            let mut model = BTreeMap::new();
+            for _ in 0..500 {
+                let k: u16 = rand::random();
+                tree.insert(k, ());
+                model.insert(k, ());
+                check_tree(&tree);
+            }
+            assert!(tree.keys().eq(model.keys()));
+            let (_, existed) = tree.entry_or_insert_reporting(7, ());
+            assert_eq!(existed, model.contains_key(&7));
+            assert_eq!(tree.get(&7), Some(&()));
+            assert_eq!(tree.len(), model.len() + usize::from(!existed));
+            check_tree(&tree);
+            assert_eq!(tree.strategy(), strategy);
+        }
+    }
+
+    #[test]
+    fn tree_strategy_rebuild_keeps_strategy_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            tree.insert(k, k);
+        }
+        tree.retain_top_n_by_value(10);
+        assert_eq!(tree.strategy(), BalanceStrategy::Avl);
+        check_tree(&tree);
+        tree.insert(200, 200);
+        check_tree(&tree);
+    }
+
+    #[test]
+    fn node_rotate_updates_cached_fields_pass() {
+        let mut tree = Tree::new();
+        for &k in &[3, 2, 5, 4, 7, 6] {
+            tree.insert(k, ());
+        }
+        rotate_l(&mut tree.root);
+        check_tree(&tree);
+        rotate_r(&mut tree.root);
+        check_tree(&tree);
+    }
+
+    #[derive(Debug, Eq)]
+    struct NoCase(String);
+
+    impl Ord for NoCase {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.to_lowercase().cmp(&other.0.to_lowercase())
+        }
+    }
+
+    impl PartialOrd for NoCase {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl PartialEq for NoCase {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    #[test]
+    fn tree_entry_replace_key_pass() {
+        let mut tree = Tree::new();
+        for name in &["ALICE", "bob", "Carol"] {
+            tree.insert(NoCase(String::from(*name)), name.len());
+        }
+        match tree.entry(NoCase(String::from("alice"))) {
+            Entry::Occupied(mut e) => {
+                *e.get_mut() += 1;
+                let old = e.replace_key(NoCase(String::from("Alice")));
+                assert_eq!(old.0, "ALICE");
+            }
+            Entry::Vacant(_) => panic!("alice should be present"),
+        }
+        assert_eq!(tree.get(&NoCase(String::from("aLiCe"))), Some(&6));
+        assert_eq!(tree.get(&NoCase(String::from("BOB"))), Some(&3));
+        let keys: Vec<&str> = tree.iter().map(|(k, _)| k.0.as_str()).collect();
+        assert_eq!(keys, vec!["Alice", "bob", "Carol"]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_stream_in_order_no_alloc_pass() {
+        let mut tree = Tree::new();
+        for _ in 0..500 {
+            let k: u16 = rand::random();
+            tree.insert(k, k);
+        }
+        let before = tree.clone();
+        let expected: Vec<u16> = tree.iter().map(|(&k, _)| k).collect();
+        let mut seen = Vec::with_capacity(expected.len());
+
+        let allocs = alloc_count::get();
+        let mut stream = tree.stream_in_order();
+        while let Some((&k, &v)) = stream.next() {
+            assert_eq!(k, v);
+            seen.push(k);
+        }
+        drop(stream);
+        assert_eq!(alloc_count::get(), allocs);
+
+        assert_eq!(seen, expected);
+        assert_eq!(tree.root, before.root);
+    }
+
+    #[test]
+    fn tree_stream_in_order_drop_restores_pass() {
+        let mut tree = Tree::new();
+        for &k in &[50, 30, 70, 20, 40, 60, 80, 35, 45, 65] {
+            tree.insert(k, ());
+        }
+        let before = tree.clone();
+        for stop in 0..=tree.len() {
+            let mut stream = tree.stream_in_order();
+            for _ in 0..stop {
+                assert!(stream.next().is_some());
+            }
+            drop(stream);
+            assert_eq!(tree.root, before.root);
+        }
+        assert!(Tree::<i32, ()>::new().stream_in_order().next().is_none());
+    }
+
+    #[test]
+    fn tree_weighted_select_pass() {
+        let mut tree = Tree::new();
+        for &(k, w) in &[('c', 3), ('a', 1), ('d', 0), ('b', 2)] {
+            tree.insert(k, w);
+        }
+        assert_eq!(tree.total_weight(|&w| w), 6);
+        assert_eq!(Tree::<u8, u64>::new().total_weight(|&w| w), 0);
+
+        let picks: Vec<char> = (0..6)
+            .map(|t| *tree.weighted_select(t, |&w| w).unwrap().0)
+            .collect();
+        assert_eq!(picks, vec!['a', 'b', 'b', 'c', 'c', 'c']);
+        assert_eq!(tree.weighted_select(6, |&w| w), None);
+        assert_eq!(tree.weighted_select(0, |_| 0), None);
+    }
+
+    #[test]
+    fn tree_split_into_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            tree.insert(k, k * 2);
+        }
+        let expected: Vec<(i32, i32)> = tree.iter().map(|(&k, &v)| (k, v)).collect();
+        let parts = tree.split_into(4);
+        assert_eq!(parts.len(), 4);
+
+        let mut union = Vec::new();
+        for part in &parts {
+            assert_eq!(part.len(), 25);
+            check_tree(part);
+            union.extend(part.iter().map(|(&k, &v)| (k, v)));
+        }
+        assert_eq!(union, expected);
+
+        let parts = Tree::with(1, ()).split_into(3);
+        assert_eq!(
+            parts.iter().map(Tree::len).collect::<Vec<_>>(),
+            vec![1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn tree_recombine_pass() {
+        let original = Tree::from_sorted((0..100).map(|k| (k, k * 3)).collect());
+        let tree = recombine(original.clone().split_into(4));
+        assert_eq!(tree, original);
+        check_tree(&tree);
+
+        let mut avl = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in (0..50).rev() {
+            avl.insert(k, ());
+        }
+        let tree = recombine(avl.split_into(7));
+        assert_eq!(tree.strategy(), BalanceStrategy::Avl);
+        assert_eq!(tree.len(), 50);
+        check_tree(&tree);
+        assert!(recombine(Vec::<Tree<u8, ()>>::new()).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn tree_recombine_out_of_order_fail() {
+        recombine(vec![Tree::with(2, ()), Tree::with(1, ())]);
+    }
+
+    #[test]
+    #[cfg(feature = "probe")]
+    fn tree_comparison_count_pass() {
+        let mut skewed = Tree::new();
+        let mut balanced = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..64 {
+            skewed.insert(k, ());
+            balanced.insert(k, ());
+        }
+        skewed.set_probe_counter(true);
+        balanced.set_probe_counter(true);
+        for k in 0..64 {
+            assert!(skewed.get(&k).is_some());
+            assert!(balanced.get(&k).is_some());
+        }
+        // Finding key k in the list takes k + 1 comparisons.
+        assert_eq!(skewed.comparison_count(), (1..=64).sum::<u64>());
+        // Every lookup in the balanced tree costs at most its height.
+        let levels = balanced.height() as u64 + 1;
+        assert!(balanced.comparison_count() <= 64 * levels);
+        assert!(balanced.comparison_count() * 4 < skewed.comparison_count());
+
+        balanced.set_probe_counter(false);
+        balanced.get(&3);
+        assert_eq!(balanced.comparison_count(), 0);
+
+        // Counting must not cost the tree its auto traits.
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Tree<u32, ()>>();
+    }
+
+    #[test]
+    fn tree_remove_pass() {
+        let mut tree = Tree::new();
+        for &k in &[50, 30, 70, 20, 40, 60, 80, 35, 45] {
+            tree.insert(k, k * 10);
+        }
+        // The root has two children.
+        assert_eq!(tree.remove(&50), Some(500));
+        assert_eq!(tree.root.as_ref().unwrap().k, 60);
+        // An internal node with two children.
+        assert_eq!(tree.remove(&30), Some(300));
+        // A node with one child, then a leaf.
+        assert_eq!(tree.remove(&70), Some(700));
+        assert_eq!(tree.remove(&45), Some(450));
+        assert_eq!(tree.remove(&45), None);
+        assert_eq!(tree.remove(&99), None);
+        check_tree(&tree);
+
+        let keys: Vec<i32> = tree.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec![20, 35, 40, 60, 80]);
+        assert_eq!(tree.len(), 5);
+        for k in keys {
+            assert_eq!(tree.remove(&k), Some(k * 10));
+        }
+        assert!(tree.is_empty());
+        assert_eq!(Tree::<i32, ()>::new().remove(&1), None);
+    }
+
+    #[test]
+    fn tree_strategy_random_remove_pass() {
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            let mut expected = BTreeMap::new();
+            for _ in 0..2000 {
+                let k: u8 = rand::random();
+                if rand::random() {
+                    assert_eq!(tree.insert(k, k), expected.insert(k, k));
+                } else {
+                    assert_eq!(tree.remove(&k), expected.remove(&k));
+                }
+            }
+            check_tree(&tree);
+            assert!(tree.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn tree_closest_n_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in (0..100).step_by(10) {
+            tree.insert(k, k / 10);
+        }
+        let closest =
+            |k, n| -> Vec<i32> { tree.closest_n(&k, n).into_iter().map(|(&k, _)| k).collect() };
+        assert_eq!(closest(44, 3), vec![40, 50, 30]);
+        assert_eq!(closest(40, 3), vec![40, 30, 50]);
+        assert_eq!(closest(-5, 2), vec![0, 10]);
+        assert_eq!(closest(95, 3), vec![90, 80, 70]);
+        assert_eq!(closest(44, 0), Vec::<i32>::new());
+        assert_eq!(closest(44, 20).len(), 10);
+        assert_eq!(tree.closest_n(&44, 1), vec![(&40, &4)]);
+        assert!(Tree::<i32, ()>::new().closest_n(&1, 3).is_empty());
+    }
+
+    #[test]
+    fn tree_get_mut_pass() {
+        let mut tree = Tree::new();
+        for (i, word) in ["owl", "bat", "yak", "emu"].iter().enumerate() {
+            tree.insert(String::from(*word), i);
+        }
+        *tree.get_mut("yak").unwrap() += 10;
+        *tree.get_mut("bat").unwrap() = 7;
+        assert_eq!(tree.get("yak"), Some(&12));
+        assert_eq!(tree.get("bat"), Some(&7));
+        assert_eq!(tree.get("owl"), Some(&0));
+        assert_eq!(tree.get_mut("cat"), None);
+    }
+
+    #[test]
+    fn tree_degeneracy_ratio_pass() {
+        let mut sorted = Tree::new();
+        let mut balanced = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..1023 {
+            sorted.insert(k, ());
+            balanced.insert(k, ());
+        }
+        assert_eq!(sorted.degeneracy_ratio(), 1022.0 / 10.0);
+        assert_eq!(balanced.degeneracy_ratio(), 9.0 / 10.0);
+        balanced.insert(1023, ());
+        assert!(balanced.degeneracy_ratio() < 1.5);
+
+        let perfect = Tree::from_sorted((0..15).map(|k| (k, ())).collect());
+        assert_eq!(perfect.degeneracy_ratio(), 3.0 / 4.0);
+        assert_eq!(Tree::<u8, ()>::new().degeneracy_ratio(), 0.0);
+        assert_eq!(Tree::with(1, ()).degeneracy_ratio(), 0.0);
+    }
+
+    #[test]
+    fn tree_contains_key_pass() {
+        let mut tree = Tree::new();
+        assert!(!tree.contains_key(&0));
+        for k in (0..20).step_by(2) {
+            tree.insert(k, [0u8; 64]);
+        }
+        for k in 0..20 {
+            assert_eq!(tree.contains_key(&k), k % 2 == 0);
+        }
+        tree.remove(&4);
+        assert!(!tree.contains_key(&4));
+    }
+
+    #[test]
+    fn tree_modify_default_pass() {
+        let mut by_len: Tree<usize, Vec<&str>> = Tree::with_strategy(BalanceStrategy::Avl);
+        for word in "the quick brown fox jumps over the lazy dog".split(' ') {
+            by_len.modify_default(word.len(), |words| words.push(word));
+        }
+        assert_eq!(by_len.get(&3), Some(&vec!["the", "fox", "the", "dog"]));
+        assert_eq!(by_len.get(&5), Some(&vec!["quick", "brown", "jumps"]));
+        assert_eq!(by_len.get(&4), Some(&vec!["over", "lazy"]));
+        assert_eq!(by_len.len(), 3);
+        check_tree(&by_len);
+
+        by_len.modify_default(9, |_| ());
+        assert_eq!(by_len.get(&9), Some(&Vec::new()));
+    }
+
+    #[test]
+    fn tree_keys_pass() {
+        let mut tree = Tree::new();
+        let mut expected = Vec::new();
+        for _ in 0..200 {
+            let k: u16 = rand::random();
+            tree.insert(k, ());
+            expected.push(k);
+        }
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(tree.keys().copied().collect::<Vec<_>>(), expected);
+        assert_eq!(Tree::<u8, ()>::new().keys().next(), None);
+    }
+
+    #[test]
+    fn tree_missing_in_range_pass() {
+        let mut tree = Tree::new();
+        for &k in &[1u8, 2, 4, 5] {
+            tree.insert(k, ());
+        }
+        let next = |&k: &u8| k + 1;
+        assert_eq!(tree.missing_in_range(1, 5, next), vec![3]);
+        assert_eq!(tree.missing_in_range(0, 7, next), vec![0, 3, 6, 7]);
+        assert_eq!(tree.missing_in_range(4, 5, next), Vec::<u8>::new());
+        assert_eq!(tree.missing_in_range(5, 1, next), Vec::<u8>::new());
+        assert_eq!(tree.missing_in_range(3, 3, next), vec![3]);
+        assert_eq!(
+            tree.missing_in_range(250, 255, next),
+            vec![250, 251, 252, 253, 254, 255]
+        );
+        let evens = |&k: &u8| k + 2;
+        assert_eq!(tree.missing_in_range(0, 8, evens), vec![0, 6, 8]);
+        assert_eq!(tree.missing_in_range(0, 7, evens), vec![0, 6]);
+    }
+
+    #[test]
+    fn tree_values_pass() {
+        let mut tree = Tree::new();
+        for &(k, v) in &[(30, "thirty"), (10, "ten"), (40, "forty"), (20, "twenty")] {
+            tree.insert(k, v);
+        }
+        let values: Vec<&str> = tree.values().copied().collect();
+        assert_eq!(values, vec!["ten", "twenty", "thirty", "forty"]);
+        assert_eq!(tree.values().map(|v| v.len()).sum::<usize>(), 20);
+    }
+
+    #[test]
+    fn tree_insert_reporting_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        let report = tree.insert_reporting(10, ());
+        assert_eq!((report.old, report.rotations, report.height), (None, 0, 0));
+        assert_eq!(tree.insert_reporting(20, ()).rotations, 0);
+        // 10 -> 20 -> 30 is a right-right chain: one single rotation.
+        let report = tree.insert_reporting(30, ());
+        assert_eq!((report.rotations, report.height), (1, 1));
+        assert_eq!(tree.insert_reporting(5, ()).rotations, 0);
+        // 20 -> 10 -> 5 -> 7 makes 5 right-heavy under a left-heavy 10:
+        // a double rotation.
+        let report = tree.insert_reporting(7, ());
+        assert_eq!((report.rotations, report.height), (2, 2));
+        check_tree(&tree);
+
+        let report = tree.insert_reporting(7, ());
+        assert_eq!((report.old, report.rotations), (Some(()), 0));
+
+        let mut plain = Tree::new();
+        for k in 0..10 {
+            assert_eq!(plain.insert_reporting(k, ()).rotations, 0);
+        }
+        assert_eq!(plain.insert_reporting(10, ()).height, 10);
+    }
+
+    #[test]
+    fn tree_values_mut_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for k in 0..100 {
+            tree.insert(k, k * 2);
+        }
+        for v in tree.values_mut() {
+            *v += 1;
+        }
+        assert!(tree.iter().all(|(&k, &v)| v == k * 2 + 1));
+        assert_eq!(tree.values_mut().count(), 100);
+        assert_eq!(Tree::<u8, u8>::new().values_mut().next(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_into_sorted_slice_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for _ in 0..1000 {
+            let k: u16 = rand::random();
+            tree.insert(k, u32::from(k) * 3);
+        }
+        let original = tree.clone();
+
+        let allocs = alloc_count::get();
+        let frozen = tree.into_sorted_slice();
+        // One allocation for the slice, one for the traversal stack.
+        assert_eq!(alloc_count::get() - allocs, 2);
+
+        assert_eq!(frozen.len(), original.len());
+        for k in 0..=u16::MAX {
+            assert_eq!(frozen.get(&k), original.get(&k));
+        }
+        let expected: Vec<(u16, u32)> = original.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(frozen.as_slice(), &expected[..]);
+        let in_range = original
+            .iter()
+            .filter(|(&k, _)| (100..=20000).contains(&k))
+            .count();
+        assert_eq!(frozen.range(100..=20000).len(), in_range);
+        assert!(frozen
+            .range((Bound::Excluded(5), Bound::Included(5)))
+            .is_empty());
+    }
+
+    #[test]
+    fn tree_iter_mut_pass() {
+        let mut tree = Tree::new();
+        for _ in 0..300 {
+            let k: u16 = rand::random();
+            tree.insert(k, Vec::new());
+        }
+        let mut prev = None;
+        for (k, v) in tree.iter_mut() {
+            assert!(prev < Some(*k));
+            prev = Some(*k);
+            v.push(*k);
+        }
+        assert!(tree.iter().all(|(k, v)| *v == [*k]));
+        check_tree(&tree);
+    }
+
+    #[cfg(feature = "std")]
+    std::thread_local! {
+        /// The stored key that `Touchy::POISON` panics on meeting.
+        static TRIPWIRE: core::cell::Cell<Option<u32>> = const { core::cell::Cell::new(None) };
+    }
+
+    /// A key whose comparisons panic when `POISON` meets the armed
+    /// `TRIPWIRE` key, so a lookup for `POISON` unwinds from wherever
+    /// the tripwire sits on its path.
+    #[cfg(feature = "std")]
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    struct Touchy(u32);
+
+    #[cfg(feature = "std")]
+    impl Touchy {
+        const POISON: u32 = 13;
+    }
+
+    #[cfg(feature = "std")]
+    impl Ord for Touchy {
+        fn cmp(&self, other: &Self) -> Ordering {
+            if let Some(wire) = TRIPWIRE.with(|wire| wire.get()) {
+                let pair = (self.0.min(other.0), self.0.max(other.0));
+                if pair == (Self::POISON.min(wire), Self::POISON.max(wire)) {
+                    panic!("compared the poisoned key");
+                }
+            }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl PartialOrd for Touchy {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_panicking_comparator_pass() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            // Every value is a clone of `live`, so its strong count is
+            // one more than the number of values still alive.
+            let live = Rc::new(());
+            let mut tree = Tree::with_strategy(strategy);
+            for k in (0..200)
+                .map(|k| k * 7 % 200)
+                .filter(|&k| k != Touchy::POISON)
+            {
+                tree.insert(Touchy(k), Rc::clone(&live));
+            }
+            let len = tree.len();
+            let keys: Vec<u32> = tree.keys().map(|k| k.0).collect();
+
+            // Arm the deepest key on `POISON`'s search path, so the
+            // panic comes after the descent has borrowed several nodes.
+            let mut path = Vec::new();
+            let mut curr = tree.root.as_deref();
+            while let Some(node) = curr {
+                path.push(node.k.0);
+                curr = if Touchy::POISON < node.k.0 {
+                    node.l.as_deref()
+                } else {
+                    node.r.as_deref()
+                };
+            }
+            assert!(
+                path.len() > 3,
+                "{:?}: tripwire would sit near the root",
+                strategy
+            );
+            TRIPWIRE.with(|wire| wire.set(path.last().copied()));
+
+            let mut borrowed = AssertUnwindSafe(&mut tree);
+            let value = Rc::clone(&live);
+            let inserted = catch_unwind(move || borrowed.insert(Touchy(Touchy::POISON), value));
+            assert!(inserted.is_err());
+            assert!(tree.validate());
+            assert!(tree.keys().map(|k| k.0).eq(keys.iter().copied()));
+            assert_eq!(Rc::strong_count(&live), len + 1);
+
+            let mut borrowed = AssertUnwindSafe(&mut tree);
+            let removed = catch_unwind(move || borrowed.remove(&Touchy(Touchy::POISON)));
+            assert!(removed.is_err());
+            assert!(tree.validate());
+            assert_eq!(tree.len(), len);
+            assert_eq!(Rc::strong_count(&live), len + 1);
+
+            TRIPWIRE.with(|wire| wire.set(None));
+            assert!(tree.remove(&Touchy(14)).is_some());
+            assert!(tree.validate());
+            drop(tree);
+            assert_eq!(Rc::strong_count(&live), 1);
+        }
+    }
+
+    #[test]
+    fn tree_into_iter_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for &word in &["pear", "fig", "apple", "kiwi", "date"] {
+            tree.insert(String::from(word), String::from(word) + "s");
+        }
+        let entries: Vec<(String, String)> = tree.into_iter().collect();
+        let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["apple", "date", "fig", "kiwi", "pear"]);
+        assert!(entries
+            .iter()
+            .all(|(k, v)| *v == String::from(k.as_str()) + "s"));
+
+        let mut partial = Tree::new().into_iter();
+        assert_eq!(partial.next(), None::<(u8, ())>);
+        let mut partial = Tree::with(1, String::from("one")).into_iter();
+        assert_eq!(partial.next(), Some((1, String::from("one"))));
+        assert_eq!(partial.next(), None);
+    }
+
+    #[test]
+    fn tree_apply_deltas_pass() {
+        let mut tree = Tree::new();
+        for k in 0..10u32 {
+            tree.insert(k, 100i64);
+        }
+        let deltas = vec![(2, 5), (7, -30), (2, 1), (42, 1000)];
+        tree.apply_deltas(deltas, |v, d| *v += d);
+        let values: Vec<i64> = tree.values().copied().collect();
+        assert_eq!(
+            values,
+            vec![100, 100, 106, 100, 100, 100, 100, 70, 100, 100]
+        );
+        assert_eq!(tree.len(), 10);
+    }
+
+    #[test]
+    fn tree_iter_with_depth_pass() {
+        let tree = Tree::from_sorted((0..31).map(|k| (k, ())).collect());
+        let depths: Vec<(i32, usize)> = tree.iter_with_depth().map(|(&k, _, d)| (k, d)).collect();
+        assert_eq!(depths.len(), 31);
+        assert!(depths.contains(&(15, 0)));
+        let max_depth = tree.height();
+        assert_eq!(max_depth, 4);
+        for node in tree.nodes() {
+            let depth = depths.iter().find(|(k, _)| *k == node.k).unwrap().1;
+            let is_leaf = node.l.is_none() && node.r.is_none();
+            assert_eq!(is_leaf, depth == max_depth);
+        }
+        // In-order traversal of a perfect tree alternates leaves with
+        // internal nodes.
+        assert!(depths.iter().step_by(2).all(|&(_, d)| d == max_depth));
+    }
+
+    #[test]
+    fn tree_from_iter_pass() {
+        let pairs = vec![(3, "c"), (1, "a"), (2, "b"), (1, "z")];
+        let tree: Tree<_, _> = pairs.into_iter().collect();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(&1), Some(&"z"));
+        assert!(tree.keys().copied().eq(1..=3));
+        check_tree(&tree);
+        assert!(core::iter::empty::<(u8, u8)>()
+            .collect::<Tree<_, _>>()
+            .is_empty());
+    }
+
+    #[test]
+    fn tree_remap_keys_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in -20..20 {
+            tree.insert(k, k * 2);
+        }
+        let negated = tree.clone().remap_keys(|&k| -k);
+        let keys: Vec<i32> = negated.keys().copied().collect();
+        assert_eq!(keys, (-19..=20).collect::<Vec<_>>());
+        assert!(negated.iter().all(|(&k, &v)| v == -k * 2));
+        assert_eq!(negated.strategy(), BalanceStrategy::Avl);
+        check_tree(&negated);
+
+        // -20..0 maps to 0 and 0..20 maps to 1; the largest old key wins.
+        let collapsed = tree.remap_keys(|&k| i32::from(k >= 0));
+        assert_eq!(collapsed.len(), 2);
+        assert_eq!(collapsed.get(&0), Some(&-2));
+        assert_eq!(collapsed.get(&1), Some(&38));
+    }
+
+    #[test]
+    fn tree_extend_pass() {
+        let mut tree: Tree<u32, u32> = (0..10).map(|k| (k * 2, k)).collect();
+        tree.extend((0..10).map(|k| (k * 3, 100 + k)));
+        assert_eq!(tree.len(), 16);
+        assert_eq!(tree.get(&6), Some(&102));
+        assert_eq!(tree.get(&4), Some(&2));
+        check_tree(&tree);
+
+        let other: Tree<u32, u32> = (100..105).map(|k| (k, k)).collect();
+        tree.extend(other.iter());
+        tree.extend(&other);
+        assert_eq!(tree.len(), 21);
+        let keys: Vec<u32> = tree.keys().copied().collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn tree_entry_word_count_pass() {
+        let text = "the cat saw the dog and the dog saw the cat run";
+        let mut counts = Tree::with_strategy(BalanceStrategy::Avl);
+        for word in text.split(' ') {
+            *counts.entry(String::from(word)).or_insert(0) += 1;
+        }
+        let expected = vec![
+            ("and", 1),
+            ("cat", 2),
+            ("dog", 2),
+            ("run", 1),
+            ("saw", 2),
+            ("the", 4),
+        ];
+        let actual: Vec<(&str, i32)> = counts.iter().map(|(k, &v)| (k.as_str(), v)).collect();
+        assert_eq!(actual, expected);
+        check_tree(&counts);
+
+        assert_eq!(counts.entry(String::from("cat")).key(), "cat");
+        let modified = counts
+            .entry(String::from("cat"))
+            .and_modify(|v| *v *= 10)
+            .or_insert(0);
+        assert_eq!(*modified, 20);
+        counts
+            .entry(String::from("zebra"))
+            .and_modify(|v| *v *= 10)
+            .or_default();
+        assert_eq!(counts.get("zebra"), Some(&0));
+        check_tree(&counts);
+    }
+
+    #[test]
+    fn tree_entry_vacant_insert_pass() {
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            let mut expected = BTreeMap::new();
+            for _ in 0..1000 {
+                let k: u8 = rand::random();
+                *tree.entry(k).or_insert(0) += 1;
+                *expected.entry(k).or_insert(0) += 1;
+            }
+            check_tree(&tree);
+            assert!(tree.iter().eq(expected.iter()));
+        }
+    }
+
+    #[test]
+    fn tree_compare_and_swap_pass() {
+        let mut tree = Tree::new();
+        tree.insert(String::from("config"), String::from("v1"));
+        tree.insert(String::from("schema"), String::from("v7"));
+
+        let v1 = String::from("v1");
+        assert_eq!(
+            tree.compare_and_swap("config", &v1, String::from("v2")),
+            Ok(())
+        );
+        assert_eq!(tree.get("config").map(String::as_str), Some("v2"));
+
+        let current = tree.compare_and_swap("config", &v1, String::from("v3"));
+        assert_eq!(current, Err(Some(String::from("v2"))));
+        assert_eq!(tree.get("config").map(String::as_str), Some("v2"));
+
+        assert_eq!(
+            tree.compare_and_swap("missing", &v1, String::from("v9")),
+            Err(None)
+        );
+        assert!(!tree.contains_key("missing"));
+        assert_eq!(tree.get("schema").map(String::as_str), Some("v7"));
+    }
+
+    #[test]
+    fn tree_clear_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for k in 0..50 {
+            tree.insert(k, k);
+        }
+        tree.clear();
+        assert!(tree.is_empty());
+        assert_eq!(tree.len(), 0);
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.strategy(), BalanceStrategy::WeightBalanced);
+
+        for k in 0..50 {
+            tree.insert(k, k + 1);
+        }
+        assert_eq!(tree.len(), 50);
+        assert_eq!(tree.get(&7), Some(&8));
+        check_tree(&tree);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_node_pool_allocs_pass() {
+        const TREES: usize = 100;
+        const KEYS: u32 = 50;
+
+        let allocs = alloc_count::get();
+        for _ in 0..TREES {
+            let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+            for k in 0..KEYS {
+                tree.insert(k * 7 % KEYS, k);
+            }
+            assert_eq!(tree.len(), KEYS as usize);
+        }
+        let boxed = alloc_count::get() - allocs;
+
+        let mut pool = NodePool::with_capacity(KEYS as usize);
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        let mut pooled_allocs = 0;
+        for _ in 0..TREES {
+            let mut pooled = tree.with_pool(&mut pool);
+            let allocs = alloc_count::get();
+            for k in 0..KEYS {
+                pooled.insert(k * 7 % KEYS, k);
+            }
+            pooled_allocs += alloc_count::get() - allocs;
+            assert_eq!(pooled.len(), KEYS as usize);
+            assert!(pooled.validate());
+            let allocs = alloc_count::get();
+            pooled.clear();
+            pooled_allocs += alloc_count::get() - allocs;
+        }
+
+        assert_eq!(boxed, TREES * KEYS as usize);
+        assert_eq!(pooled_allocs, 0);
+        assert_eq!(pool.len(), KEYS as usize);
+    }
+
+    #[test]
+    fn tree_node_pool_remove_pass() {
+        let mut pool = NodePool::new();
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        {
+            let mut pooled = tree.with_pool(&mut pool);
+            for k in 0..20 {
+                assert_eq!(pooled.insert(k, k * 10), None);
+            }
+            assert_eq!(pooled.insert(3, 33), Some(30));
+            for k in (0..20).step_by(2) {
+                assert_eq!(pooled.remove(&k), Some(k * 10));
+            }
+            assert_eq!(pooled.remove(&0), None);
+            assert!(pooled.keys().copied().eq((1..20).step_by(2)));
+            assert_eq!(pooled.get(&3), Some(&33));
+        }
+        check_tree(&tree);
+        assert_eq!(pool.len(), 10);
+
+        // Nodes taken from the pool behave like any other once the pool
+        // is gone.
+        tree.insert(100, 1000);
+        assert_eq!(tree.remove(&3), Some(33));
+        pool.recycle(tree);
+        assert_eq!(pool.len(), 20);
+    }
+
+    #[test]
+    fn tree_first_last_key_value_pass() {
+        let mut tree = Tree::new();
+        assert_eq!(tree.first_key_value(), None);
+        assert_eq!(tree.last_key_value(), None);
+
+        tree.insert(50, 'm');
+        assert_eq!(tree.first_key_value(), Some((&50, &'m')));
+        assert_eq!(tree.last_key_value(), Some((&50, &'m')));
+
+        for &(k, v) in &[(30, 'f'), (70, 't'), (40, 'h'), (10, 'a'), (90, 'z')] {
+            tree.insert(k, v);
+        }
+        assert_eq!(tree.first_key_value(), Some((&10, &'a')));
+        assert_eq!(tree.last_key_value(), Some((&90, &'z')));
+        tree.remove(&10);
+        tree.remove(&90);
+        assert_eq!(tree.first_key_value(), Some((&30, &'f')));
+        assert_eq!(tree.last_key_value(), Some((&70, &'t')));
+    }
+
+    #[test]
+    fn tree_validate_reverse_order_pass() {
+        use core::cmp::Reverse;
+
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            tree.insert(Reverse(k), ());
+        }
+        assert!(tree.validate());
+        check_tree(&tree);
+
+        // The same keys fail a check that ignores the tree's ordering.
+        let inner: Vec<i32> = tree.keys().map(|k| k.0).collect();
+        assert!(!inner.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(tree.first_key_value(), Some((&Reverse(99), &())));
+    }
+
+    #[test]
+    fn tree_pop_first_pass() {
+        for &strategy in &[BalanceStrategy::None, BalanceStrategy::Avl] {
+            let mut tree = Tree::with_strategy(strategy);
+            for _ in 0..500 {
+                let k = rand::random::<u16>();
+                tree.insert(k, u32::from(k) * 2);
+            }
+            let mut popped = Vec::new();
+            while let Some((k, v)) = tree.pop_first() {
+                assert_eq!(v, u32::from(k) * 2);
+                popped.push(k);
+                check_tree(&tree);
+            }
+            assert!(popped.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn tree_pop_last_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for k in 0..200 {
+            tree.insert(k, ());
+        }
+        for k in (100..200).rev() {
+            assert_eq!(tree.pop_last(), Some((k, ())));
+            check_tree(&tree);
+        }
+        assert_eq!(tree.pop_first(), Some((0, ())));
+        assert_eq!(tree.len(), 99);
+    }
+
+    #[test]
+    fn tree_clone_range_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            tree.insert(k, k * 2);
+        }
+        let before = tree.clone();
+
+        let window = tree.clone_range(40..=60);
+        check_tree(&window);
+        assert_eq!(window.strategy, BalanceStrategy::Avl);
+        let expected: Vec<_> = (40..=60).map(|k| (k, k * 2)).collect();
+        assert_eq!(window.into_sorted_vec(), expected);
+        assert_eq!(tree, before);
+
+        assert!(tree.clone_range(50..50).is_empty());
+        assert!(tree.clone_range(100..).is_empty());
+        assert_eq!(tree.clone_range(..).len(), 100);
+        assert_eq!(
+            tree.clone_range((Bound::Excluded(98), Bound::Unbounded))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn tree_predecessor_successor_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in (0..100).map(|k| k * 2) {
+            tree.insert(k, k / 2);
+        }
+
+        // Keys at the extremes.
+        assert_eq!(tree.predecessor(&0), None);
+        assert_eq!(tree.successor(&0), Some((&2, &1)));
+        assert_eq!(tree.predecessor(&198), Some((&196, &98)));
+        assert_eq!(tree.successor(&198), None);
+
+        // Keys not in the tree, inside and outside its span.
+        assert_eq!(tree.predecessor(&51), Some((&50, &25)));
+        assert_eq!(tree.successor(&51), Some((&52, &26)));
+        assert_eq!(tree.predecessor(&-1), None);
+        assert_eq!(tree.successor(&-1), Some((&0, &0)));
+        assert_eq!(tree.predecessor(&500), Some((&198, &99)));
+        assert_eq!(tree.successor(&500), None);
+
+        for k in 1..198 {
+            let pred = tree.predecessor(&k).map(|(&k, _)| k);
+            let succ = tree.successor(&k).map(|(&k, _)| k);
+            assert_eq!(pred, Some((k - 1) / 2 * 2));
+            assert_eq!(succ, Some(k / 2 * 2 + 2));
+        }
+
+        let empty: Tree<i32, ()> = Tree::new();
+        assert_eq!(empty.predecessor(&0), None);
+        assert_eq!(empty.successor(&0), None);
+    }
+
+    #[test]
+    fn tree_fill_defaults_pass() {
+        let mut user = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in (0..50).map(|k| k * 3) {
+            user.insert(k, "user");
+        }
+        let mut defaults = Tree::new();
+        for k in (0..75).map(|k| k * 2) {
+            defaults.insert(k, "default");
+        }
+
+        user.fill_defaults(defaults);
+        check_tree(&user);
+        assert_eq!(user.strategy, BalanceStrategy::Avl);
+        for k in 0..150 {
+            let expected = if k % 3 == 0 {
+                Some(&"user")
+            } else if k % 2 == 0 {
+                Some(&"default")
+            } else {
+                None
+            };
+            assert_eq!(user.get(&k), expected);
+        }
+
+        let mut empty = Tree::new();
+        empty.fill_defaults(Tree::with(1, 'd'));
+        assert_eq!(empty.get(&1), Some(&'d'));
+        empty.fill_defaults(Tree::new());
+        assert_eq!(empty.len(), 1);
+    }
+
+    #[test]
+    fn tree_floor_ceiling_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for k in (1..=20).map(|k| k * 10) {
+            tree.insert(k, k / 10);
+        }
+
+        // Exact matches return themselves.
+        assert_eq!(tree.floor(&10), Some((&10, &1)));
+        assert_eq!(tree.ceiling(&10), Some((&10, &1)));
+        assert_eq!(tree.floor(&200), Some((&200, &20)));
+        assert_eq!(tree.ceiling(&200), Some((&200, &20)));
+
+        // Queries in a gap return the neighbor on their side.
+        assert_eq!(tree.floor(&55), Some((&50, &5)));
+        assert_eq!(tree.ceiling(&55), Some((&60, &6)));
+
+        // Queries off either end.
+        assert_eq!(tree.floor(&9), None);
+        assert_eq!(tree.ceiling(&9), Some((&10, &1)));
+        assert_eq!(tree.floor(&201), Some((&200, &20)));
+        assert_eq!(tree.ceiling(&201), None);
+
+        for q in 10..=200 {
+            assert_eq!(tree.floor(&q).map(|(&k, _)| k), Some(q / 10 * 10));
+            assert_eq!(tree.ceiling(&q).map(|(&k, _)| k), Some((q + 9) / 10 * 10));
+        }
+    }
+
+    #[test]
+    fn tree_entries_by_value_pass() {
+        let mut scores = Tree::new();
+        for player in 0..200u32 {
+            scores.insert(player, rand::random::<u8>());
+        }
+        let board = scores.entries_by_value();
+        assert_eq!(board.len(), scores.len());
+        assert_eq!(board[0].1, scores.values().max().unwrap());
+        for pair in board.windows(2) {
+            let ((ka, va), (kb, vb)) = (pair[0], pair[1]);
+            assert!(va > vb || (va == vb && ka < kb));
+        }
+    }
+
+    #[test]
+    fn tree_range_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in (0..100).map(|k| k * 2) {
+            tree.insert(k, k + 1);
+        }
+        fn keys<'a>(range: Range<'a, i32, i32>) -> Vec<i32> {
+            range
+                .map(|(&k, &v)| {
+                    assert_eq!(v, k + 1);
+                    k
+                })
+                .collect()
+        }
+        let evens = |lo: i32, hi: i32| (lo..hi).filter(|k| k % 2 == 0).collect::<Vec<_>>();
+
+        assert_eq!(keys(tree.range(10..20)), evens(10, 20));
+        assert_eq!(keys(tree.range(11..21)), evens(11, 21));
+        assert_eq!(keys(tree.range(10..=20)), evens(10, 21));
+        assert_eq!(keys(tree.range(..7)), evens(0, 7));
+        assert_eq!(keys(tree.range(..=8)), evens(0, 9));
+        assert_eq!(keys(tree.range(190..)), evens(190, 200));
+        assert_eq!(keys(tree.range(..)), evens(0, 200));
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(10), Bound::Excluded(20)))),
+            evens(11, 20)
+        );
+
+        // Empty windows, including ones between adjacent keys and off
+        // either end.
+        assert!(keys(tree.range(11..12)).is_empty());
+        assert!(keys(tree.range(10..10)).is_empty());
+        assert!(keys(tree.range(200..)).is_empty());
+        assert!(keys(tree.range(..0)).is_empty());
+        assert!(keys(tree.range((Bound::Excluded(10), Bound::Included(11)))).is_empty());
+        assert!(keys(Tree::new().range(..)).is_empty());
+    }
+
+    #[test]
+    fn tree_prefix_sums_pass() {
+        let mut tree = Tree::new();
+        for _ in 0..300 {
+            tree.insert(rand::random::<u16>(), u64::from(rand::random::<u8>()));
+        }
+        let sums = tree.prefix_sums();
+        assert_eq!(sums.len(), tree.len());
+        let keys: Vec<&u16> = sums.iter().map(|&(k, _)| k).collect();
+        assert_eq!(keys, tree.keys().collect::<Vec<_>>());
+        let mut running = 0;
+        for (&(_, sum), v) in sums.iter().zip(tree.values()) {
+            running += v;
+            assert_eq!(sum, running);
+        }
+        assert_eq!(sums.last().unwrap().1, tree.total_weight(|&v| v));
+
+        assert!(Tree::<u8, u8>::new().prefix_sums().is_empty());
+    }
+
+    #[test]
+    fn tree_rank_select_pass() {
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            for _ in 0..500 {
+                tree.insert(rand::random::<u16>() % 1000, ());
+            }
+            for _ in 0..200 {
+                tree.remove(&(rand::random::<u16>() % 1000));
+            }
+            check_tree(&tree);
+
+            for (i, (k, _)) in tree.iter().enumerate() {
+                assert_eq!(tree.rank(k), i);
+                assert_eq!(tree.select(tree.rank(k)), Some((k, &())));
+            }
+            for k in 0..1000 {
+                let below = tree.keys().filter(|&&key| key < k).count();
+                assert_eq!(tree.rank(&k), below);
+            }
+            assert_eq!(tree.select(tree.len()), None);
+        }
+    }
+
+    #[test]
+    fn tree_take_value_pass() {
+        let mut tree = Tree::new();
+        for k in 0..50 {
+            tree.insert(k, vec![k; 3]);
+        }
+        assert_eq!(tree.take_value(&20), Some(vec![20; 3]));
+        assert_eq!(tree.get(&20), Some(&Vec::new()));
+        assert!(tree.contains_key(&20));
+        assert_eq!(tree.len(), 50);
+        assert_eq!(tree.take_value(&20), Some(Vec::new()));
+        assert_eq!(tree.take_value(&50), None);
+        assert_eq!(tree.len(), 50);
+        check_tree(&tree);
+    }
+
+    #[test]
+    fn tree_depth_of_skewed_pass() {
+        // Sorted input with no balancing degenerates into a right spine.
+        let mut skewed = Tree::new();
+        for k in 0..1000 {
+            skewed.insert(k, ());
+        }
+        assert_eq!(skewed.height(), 999);
+        for k in 0..1000 {
+            assert_eq!(skewed.depth_of(&k), Some(k as usize));
+        }
+        assert_eq!(skewed.depth_of(&1000), None);
+
+        let mut balanced = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..1000 {
+            balanced.insert(k, ());
+        }
+        assert!(balanced.height() < 15);
+        let mut deepest = 0;
+        for (k, _, depth) in balanced.iter_with_depth() {
+            assert_eq!(balanced.depth_of(k), Some(depth));
+            deepest = deepest.max(depth);
+        }
+        assert_eq!(deepest, balanced.height());
+
+        let empty: Tree<i32, ()> = Tree::new();
+        assert_eq!(empty.height(), 0);
+        assert_eq!(empty.depth_of(&0), None);
+    }
+
+    #[test]
+    fn tree_range_filter_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..500u32 {
+            tree.insert(k, rand::random::<u8>());
+        }
+        let found: Vec<(&u32, &u8)> = tree.range_filter(100..=300, |&v| v >= 128).collect();
+        let expected: Vec<(&u32, &u8)> = tree
+            .iter()
+            .filter(|&(&k, &v)| (100..=300).contains(&k) && v >= 128)
+            .collect();
+        assert_eq!(found, expected);
+        assert!(found
+            .iter()
+            .all(|&(&k, &v)| (100..=300).contains(&k) && v >= 128));
+
+        let mut visited = 0;
+        let none: Vec<_> = tree
+            .range_filter(..10, |_| {
+                visited += 1;
+                false
+            })
+            .collect();
+        assert!(none.is_empty());
+        assert_eq!(visited, 10);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_avl_height_bound_pass() {
+        fn bound(n: usize) -> f64 {
+            1.44 * (n as f64).log2()
+        }
+
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 1..=1000 {
+            tree.insert(k, k);
+            assert!(tree.height() as f64 <= bound(tree.len()));
+        }
+        check_tree(&tree);
+
+        // Removing from one end skews the tree unless rotations repair it.
+        for k in 1..=900 {
+            assert_eq!(tree.remove(&k), Some(k));
+            assert!(tree.height() as f64 <= bound(tree.len()));
+        }
+        check_tree(&tree);
+        assert!(tree.iter().map(|(&k, _)| k).eq(901..=1000));
+        assert_eq!(tree.get(&950), Some(&950));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn tree_structural_hash_pass() {
+        let balanced = Tree::from_sorted((0..15).map(|k| (k, ())).collect());
+        let mut spine = Tree::new();
+        for k in 0..15 {
+            spine.insert(k, ());
+        }
+        assert!(balanced.iter().eq(spine.iter()));
+        assert_ne!(balanced.structural_hash(), spine.structural_hash());
+
+        // Same shape and keys hash the same, whatever the values.
+        let relabeled = Tree::from_sorted((0..15).map(|k| (k, k * 2)).collect());
+        assert_eq!(balanced.structural_hash(), relabeled.structural_hash());
+
+        // Same shape with different keys.
+        let shifted = Tree::from_sorted((1..16).map(|k| (k, ())).collect());
+        assert_ne!(balanced.structural_hash(), shifted.structural_hash());
+
+        assert_eq!(
+            Tree::<u8, ()>::new().structural_hash(),
+            Tree::<u8, ()>::new().structural_hash()
+        );
+    }
+
+    #[test]
+    fn tree_red_black_random_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        let mut reference = BTreeMap::new();
+        for _ in 0..2000 {
+            let k = rand::random::<u8>();
+            match rand::random::<u8>() % 4 {
+                0 => assert_eq!(tree.remove(&k), reference.remove(&k)),
+                1 if rand::random() => {
+                    let first = reference.keys().next().copied();
+                    let expected = first.map(|k| (k, reference.remove(&k).unwrap()));
+                    assert_eq!(tree.pop_first(), expected);
+                }
+                1 => {
+                    let last = reference.keys().next_back().copied();
+                    let expected = last.map(|k| (k, reference.remove(&k).unwrap()));
+                    assert_eq!(tree.pop_last(), expected);
+                }
+                _ => assert_eq!(tree.insert(k, k), reference.insert(k, k)),
+            }
+            check_tree(&tree);
+            #[cfg(debug_assertions)]
+            tree.assert_rb_invariants();
+        }
+        assert!(tree.iter().eq(reference.iter()));
+    }
+
+    #[test]
+    fn tree_red_black_sorted_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        let mut rotations = 0;
+        for k in 0..1000 {
+            rotations += tree.insert_reporting(k, k).rotations;
+            check_tree(&tree);
+            #[cfg(debug_assertions)]
+            tree.assert_rb_invariants();
+        }
+        // log2(1001) is just under 10.
+        assert!(tree.height() < 20);
+        assert!(rotations < 1000);
+        for k in (0..1000).rev().filter(|k| k % 3 != 0) {
+            assert_eq!(tree.remove(&k), Some(k));
+            check_tree(&tree);
+            #[cfg(debug_assertions)]
+            tree.assert_rb_invariants();
         }
+        assert!(tree.iter().map(|(&k, _)| k).eq((0..1000).step_by(3)));
     }
-}
 
-pub(crate) fn l<K, V>(root: &Option<Box<Node<K, V>>>) -> Option<&Node<K, V>> {
-    match root {
-        None => None,
-        Some(box_root) => box_root.l.as_ref().map(AsRef::as_ref),
-    }
-}
-pub(crate) fn r<K, V>(root: &Option<Box<Node<K, V>>>) -> Option<&Node<K, V>> {
-    match root {
-        None => None,
-        Some(box_root) => box_root.r.as_ref().map(AsRef::as_ref),
-    }
-}
-pub(crate) fn l_mut<K, V>(root: &mut Option<Box<Node<K, V>>>) -> Option<&mut Node<K, V>> {
-    match root {
-        None => None,
-        Some(box_root) => box_root.l.as_mut().map(Box::as_mut),
+    #[test]
+    fn tree_red_black_rebuild_pass() {
+        for len in 0..70 {
+            let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+            for k in 0..len {
+                tree.insert(k, k);
+            }
+            tree.retain_top_n_by_value(len);
+            check_tree(&tree);
+            let parts = tree.split_into(3);
+            for part in &parts {
+                check_tree(part);
+            }
+            let mut tree = recombine(parts);
+            check_tree(&tree);
+            assert_eq!(tree.len(), len);
+            tree.insert(len, len);
+            tree.remove(&0);
+            check_tree(&tree);
+        }
     }
-}
-pub(crate) fn len<K: Ord, V>(root: &Option<Box<Node<K, V>>>) -> Option<usize> {
-    root.as_ref().map(|node| node.len())
-}
-pub(crate) fn r_mut<K, V>(root: &mut Option<Box<Node<K, V>>>) -> Option<&mut Node<K, V>> {
-    match root {
-        None => None,
-        Some(box_root) => box_root.r.as_mut().map(Box::as_mut),
+
+    #[test]
+    fn tree_modify_existing_pass() {
+        let mut tree = Tree::new();
+        for k in (0..100).step_by(2) {
+            tree.insert(k, 0);
+        }
+        let modified = tree.modify_existing(0..20, |v| *v += 1);
+        assert_eq!(modified, 10);
+        for k in (0..100).step_by(2) {
+            assert_eq!(tree.get(&k), Some(&usize::from(k < 20)));
+        }
+        assert_eq!(tree.modify_existing(vec![1, 3, 101], |_| unreachable!()), 0);
+        assert_eq!(tree.modify_existing(vec![4, 4], |v| *v += 1), 2);
+        assert_eq!(tree.get(&4), Some(&3));
+        assert_eq!(tree.len(), 50);
     }
-}
-pub(crate) fn rotate_r<K, V>(root: &mut Option<Box<Node<K, V>>>) {
-    *root = match root.take() {
-        None => {
-            // Cannot rotate an empty tree
-            return;
+
+    #[test]
+    fn tree_rebalance_pass() {
+        for len in (0..70).chain(vec![1000, 1023, 1024]) {
+            let mut tree = Tree::new();
+            for k in 0..len {
+                tree.insert(k, k * 2);
+            }
+            tree.rebalance();
+            check_tree(&tree);
+            assert_eq!(tree.len(), len);
+            assert!(tree
+                .iter()
+                .map(|(&k, &v)| (k, v))
+                .eq((0..len).map(|k| (k, k * 2))));
+            // The minimum height of a tree with `len` nodes.
+            let levels = (usize::BITS - len.leading_zeros()) as usize;
+            assert_eq!(tree.height(), levels.saturating_sub(1));
         }
-        Some(mut root) => {
-            match root.l.take() {
-                None => {
-                    // Cannot right rorate if `root` doesn't have left child
-                    Some(root)
-                }
-                Some(mut pivot) => {
-                    root.l = pivot.r.take();
-                    pivot.r = Some(root);
-                    Some(pivot)
-                }
+
+        // A random shape, and strategies whose invariants must survive.
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            for _ in 0..500 {
+                tree.insert(rand::random::<u16>(), ());
             }
+            let before: Vec<u16> = tree.keys().copied().collect();
+            tree.rebalance();
+            check_tree(&tree);
+            assert!(tree.keys().eq(before.iter()));
+            tree.insert(0, ());
+            tree.remove(&before[0]);
+            check_tree(&tree);
         }
-    };
-}
+    }
 
-pub(crate) fn rotate_l<K, V>(root: &mut Option<Box<Node<K, V>>>) {
-    *root = match root.take() {
-        None => {
-            // Cannot rotate an empty tree
-            return;
+    #[test]
+    fn tree_group_by_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..30 {
+            tree.insert(k, k * 2);
         }
-        Some(mut root) => {
-            match root.r.take() {
-                None => {
-                    // Cannot left rorate if `root` doesn't have right child
-                    Some(root)
-                }
-                Some(mut pivot) => {
-                    root.r = pivot.l.take();
-                    pivot.l = Some(root);
-                    Some(pivot)
-                }
-            }
+        let groups = tree.group_by(|&k, _| k % 3);
+        check_tree(&groups);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups.strategy(), BalanceStrategy::Avl);
+        for (&rem, values) in groups.iter() {
+            let expected: Vec<i32> = (0..30).filter(|k| k % 3 == rem).map(|k| k * 2).collect();
+            assert_eq!(values, &expected);
         }
-    };
-}
 
-#[cfg(feature = "graphviz")]
-impl<'a, K, V> dot::Labeller<'a, (K, V), (K, K)> for Tree<K, V>
-where
-    K: ::std::fmt::Display + Copy,
-    V: ::std::fmt::Display + Copy,
-{
-    fn graph_id(&'a self) -> dot::Id<'a> {
-        dot::Id::new("Tree").unwrap()
+        let by_value = Tree::with(1, 'a').group_by(|_, &v| v);
+        assert_eq!(by_value.get(&'a'), Some(&vec!['a']));
+        assert!(Tree::<i32, ()>::new().group_by(|&k, _| k).is_empty());
     }
 
-    fn node_id(&'a self, n: &(K, V)) -> dot::Id<'a> {
-        dot::Id::new(format!("k_{}", n.0)).unwrap()
+    #[test]
+    fn tree_index_pass() {
+        let mut tree = Tree::new();
+        for k in 0..100 {
+            tree.insert(k, k * 3);
+        }
+        for k in 0..100 {
+            assert_eq!(tree[&k], k * 3);
+        }
+        let names = Tree::with(String::from("ann"), 1);
+        assert_eq!(names["ann"], 1);
     }
 
-    fn node_label(&'a self, n: &(K, V)) -> dot::LabelText<'a> {
-        dot::LabelText::LabelStr(::std::borrow::Cow::Owned(format!("{}", n.0)))
+    #[test]
+    #[should_panic(expected = "no entry found for key")]
+    fn tree_index_fail() {
+        let tree = Tree::with(1, 'a');
+        let _ = tree[&2];
     }
-}
 
-#[cfg(feature = "graphviz")]
-impl<'a, K, V> dot::GraphWalk<'a, (K, V), (K, K)> for Tree<K, V>
-where
-    K: ::std::fmt::Display + Copy + Ord,
-    V: ::std::fmt::Display + Copy,
-{
-    fn nodes(&'a self) -> dot::Nodes<'a, (K, V)> {
-        ::std::borrow::Cow::Owned(self.nodes().map(|&Node { k, v, .. }| (k, v)).collect())
+    #[test]
+    fn tree_get_fresh_or_insert_with_pass() {
+        let fresh = |&(at, _): &(u32, u32)| at >= 10;
+        let mut cache = Tree::with(1, (5, 50));
+        cache.insert(2, (15, 150));
+
+        // A fresh hit leaves the value alone and never calls `f`.
+        let hit = cache.get_fresh_or_insert_with(2, fresh, || unreachable!());
+        assert_eq!(*hit, (15, 150));
+        hit.1 += 1;
+        assert_eq!(cache.get(&2), Some(&(15, 151)));
+
+        // A stale value is recomputed.
+        assert_eq!(
+            *cache.get_fresh_or_insert_with(1, fresh, || (20, 51)),
+            (20, 51)
+        );
+        assert_eq!(cache.get(&1), Some(&(20, 51)));
+
+        // An absent key is inserted.
+        assert_eq!(
+            *cache.get_fresh_or_insert_with(3, fresh, || (20, 30)),
+            (20, 30)
+        );
+        assert_eq!(cache.len(), 3);
+        check_tree(&cache);
     }
 
-    fn edges(&'a self) -> dot::Edges<'a, (K, K)> {
-        let mut edges: Vec<(K, K)> = Vec::new();
-        for Node { k, l, r, .. } in self.nodes() {
-            if let Some(l) = l.as_deref() {
-                edges.push((*k, l.k));
-            }
-            if let Some(r) = r.as_deref() {
-                edges.push((*k, r.k));
-            }
+    #[test]
+    fn tree_eq_ignores_shape_pass() {
+        let ascending: Tree<_, _> = vec![(1, 'a'), (2, 'b')].into_iter().collect();
+        let descending: Tree<_, _> = vec![(2, 'b'), (1, 'a')].into_iter().collect();
+        assert_ne!(ascending.root, descending.root);
+        assert_eq!(ascending, descending);
+
+        let mut spine = Tree::new();
+        let mut avl = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            spine.insert(k, k);
+            avl.insert(99 - k, 99 - k);
         }
-        ::std::borrow::Cow::Owned(edges)
+        assert_eq!(spine, avl);
+        avl.insert(50, 0);
+        assert_ne!(spine, avl);
+        avl.insert(50, 50);
+        avl.insert(100, 100);
+        assert_ne!(spine, avl);
+        assert_ne!(avl, spine);
+        assert_eq!(Tree::<u8, u8>::new(), Tree::new());
     }
 
-    fn source(&'a self, e: &(K, K)) -> (K, V) {
-        (e.0, *self.get(&e.0).unwrap())
+    #[test]
+    fn tree_fold_range_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        for k in 0..100u64 {
+            tree.insert(k, k * k);
+        }
+        let sum = tree.fold_range(10..=20, 0, |sum, _, &v| sum + v);
+        assert_eq!(sum, (10..=20).map(|k| k * k).sum::<u64>());
+
+        let keys = tree.fold_range(..3, Vec::new(), |mut keys, &k, _| {
+            keys.push(k);
+            keys
+        });
+        assert_eq!(keys, vec![0, 1, 2]);
+        assert_eq!(tree.fold_range(200.., 7, |_, _, _| unreachable!()), 7);
     }
 
-    fn target(&'a self, e: &(K, K)) -> (K, V) {
-        (e.1, *self.get(&e.1).unwrap())
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "tree invariants violated")]
+    fn tree_debug_assert_valid_fail() {
+        // Keys on the wrong sides of the root, which no public method
+        // can produce.
+        let mut tree = Tree::from_root(Some(Box::new(Node::with_children(
+            1,
+            (),
+            Some(Box::new(Node::new(2, ()))),
+            None,
+        ))));
+        tree.insert(0, ());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[cfg(not(feature = "std"))]
-    use alloc::string::String;
+    #[test]
+    fn tree_merge_join_pass() {
+        let mut left = Tree::new();
+        for k in 0..20 {
+            left.insert(k, k * 10);
+        }
+        let mut right = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 10..30 {
+            right.insert(k, vec![k]);
+        }
+
+        let items: Vec<_> = left.merge_join(&right).collect();
+        assert_eq!(items.len(), 30);
+        for (i, item) in items.iter().enumerate() {
+            let k = i as i32;
+            match *item {
+                MergeItem::Left(&key, &v) => assert!(key == k && k < 10 && v == k * 10),
+                MergeItem::Both(&key, &v, w) => {
+                    assert!(key == k && (10..20).contains(&k) && v == k * 10);
+                    assert_eq!(w, &vec![k]);
+                }
+                MergeItem::Right(&key, w) => {
+                    assert!(key == k && k >= 20);
+                    assert_eq!(w, &vec![k]);
+                }
+            }
+        }
+
+        let empty: Tree<i32, ()> = Tree::new();
+        assert_eq!(empty.merge_join(&right).count(), 20);
+        assert!(empty.merge_join(&empty).next().is_none());
+    }
 
     #[test]
-    fn tree_eq_pass() {
-        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
-        let tree_b = Tree::with(String::from("cat"), String::from("meow"));
-        assert_eq!(tree_a, tree_b);
+    fn tree_from_iter_balanced_pass() {
+        // A fixed-stride walk visits 0..1000 in a scrambled order.
+        let shuffled: Vec<(u32, u32)> = (0..1000).map(|i| (i * 337 % 1000, i)).collect();
+        let tree = Tree::from_iter_balanced(shuffled.iter().copied());
+        check_tree(&tree);
+        assert_eq!(tree.len(), 1000);
+        // 1000 nodes need at least 10 levels.
+        assert_eq!(tree.height(), 9);
+        for &(k, i) in &shuffled {
+            assert_eq!(tree.get(&k), Some(&i));
+        }
+
+        let repeated = Tree::from_iter_balanced((0..100).map(|i| (i % 10, i)));
+        check_tree(&repeated);
+        assert_eq!(repeated.len(), 10);
+        assert!(repeated.iter().all(|(&k, &v)| v == 90 + k));
+        assert!(Tree::<u8, u8>::from_iter_balanced(Vec::new()).is_empty());
     }
 
     #[test]
-    #[should_panic]
-    fn tree_eq_fail() {
-        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
-        let tree_b = Tree::with(String::from("dog"), String::from("bark"));
-        assert_eq!(tree_a, tree_b);
+    fn tree_insert_mut_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        for k in 0..100 {
+            let (v, old) = tree.insert_mut(k, vec![k]);
+            assert_eq!(old, None);
+            v.push(k + 1);
+        }
+        check_tree(&tree);
+        for k in 0..100 {
+            assert_eq!(tree.get(&k), Some(&vec![k, k + 1]));
+        }
+
+        let (v, old) = tree.insert_mut(50, Vec::new());
+        assert_eq!(old, Some(vec![50, 51]));
+        v.push(0);
+        assert_eq!(tree.get(&50), Some(&vec![0]));
+        assert_eq!(tree.len(), 100);
     }
 
     #[test]
-    fn tree_neq_pass() {
-        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
-        let tree_b = Tree::with(String::from("dog"), String::from("bark"));
-        assert_ne!(tree_a, tree_b);
+    fn tree_iter_until_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..100 {
+            tree.insert(k, k);
+        }
+        let mut until = tree.iter_until(&50);
+        assert!(until.by_ref().map(|(&k, _)| k).eq(0..50));
+        assert_eq!(until.next(), None);
+        // The underlying traversal stopped right at the bound.
+        assert_eq!(until.iter.next().map(|node| node.k), Some(50));
+
+        assert_eq!(tree.iter_until(&0).next(), None);
+        assert_eq!(tree.iter_until(&1000).count(), 100);
+        let names = Tree::with(String::from("b"), ());
+        assert_eq!(names.iter_until("b").count(), 0);
+        assert_eq!(names.iter_until("c").count(), 1);
     }
 
     #[test]
-    #[should_panic]
-    fn tree_neq_fail() {
-        let tree_a = Tree::with(String::from("cat"), String::from("meow"));
-        let tree_b = Tree::with(String::from("cat"), String::from("meow"));
-        assert_ne!(tree_a, tree_b);
+    fn tree_node_ref_pass() {
+        fn collect<'a>(node: Option<NodeRef<'a, u16, u32>>, out: &mut Vec<(u16, u32)>) -> usize {
+            match node {
+                None => 0,
+                Some(node) => {
+                    let lh = collect(node.left(), out);
+                    out.push((*node.key(), *node.value()));
+                    let rh = collect(node.right(), out);
+                    1 + lh.max(rh)
+                }
+            }
+        }
+
+        let mut tree = Tree::with_strategy(BalanceStrategy::WeightBalanced);
+        for _ in 0..300 {
+            let k = rand::random::<u16>();
+            tree.insert(k, u32::from(k) + 1);
+        }
+        let mut pairs = Vec::new();
+        let levels = collect(tree.root(), &mut pairs);
+        assert_eq!(levels, tree.height() + 1);
+        let expected: Vec<(u16, u32)> = tree.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(pairs, expected);
+
+        let root = tree.root().unwrap();
+        assert_eq!(root.key(), &tree.root.as_ref().unwrap().k);
+        assert!(Tree::<u8, u8>::new().root().is_none());
     }
 
     #[test]
-    fn tree_insert_pass() {
-        let mut tree_root = Tree::with(1, '1');
-        tree_root.insert(0, '0');
-        tree_root.insert(2, '2');
-        let tree_root_1 = Tree(Some(Box::new(Node {
-            k: 1,
-            v: '1',
-            l: Some(Box::new(Node::new(0, '0'))),
-            r: Some(Box::new(Node::new(2, '2'))),
-        })));
-        assert_eq!(tree_root, tree_root_1);
-        assert_eq!(tree_root.len(), 3);
+    fn tree_get_pair_mut_pass() {
+        let mut tree = Tree::new();
+        for k in 0..10 {
+            tree.insert(k, k * 10);
+        }
+
+        // In key order, then reversed.
+        let (a, b) = tree.get_pair_mut(&2, &7).unwrap();
+        assert_eq!((*a, *b), (20, 70));
+        mem::swap(a, b);
+        assert_eq!(tree.get(&2), Some(&70));
+        assert_eq!(tree.get(&7), Some(&20));
+        let (a, b) = tree.get_pair_mut(&9, &0).unwrap();
+        assert_eq!((*a, *b), (90, 0));
+        *a += 1;
+        *b += 1;
+        assert_eq!(tree.get(&9), Some(&91));
+        assert_eq!(tree.get(&0), Some(&1));
+
+        // Aliasing and missing keys.
+        assert!(tree.get_pair_mut(&4, &4).is_none());
+        assert!(tree.get_pair_mut(&4, &10).is_none());
+        assert!(tree.get_pair_mut(&-1, &4).is_none());
+        assert!(tree.get_pair_mut(&-1, &-1).is_none());
+        check_tree(&tree);
+
+        // Keys whose paths part below the root, on either side.
+        let mut balanced = Tree::from_sorted((0..15).map(|k| (k, k)).collect());
+        let (a, b) = balanced.get_pair_mut(&6, &4).unwrap();
+        assert_eq!((*a, *b), (6, 4));
+        let (a, b) = balanced.get_pair_mut(&1, &3).unwrap();
+        mem::swap(a, b);
+        assert_eq!(balanced.get(&1), Some(&3));
+        assert_eq!(balanced.get(&3), Some(&1));
+        assert!(balanced.get_pair_mut(&5, &15).is_none());
     }
 
     #[test]
-    fn tree_insert_duplicate_pass() {
-        let mut tree_root = Tree::with(0, '0');
-        assert_eq!(tree_root.insert(1, '1'), None);
-        assert_eq!(tree_root.insert(1, '1'), Some('1'));
+    fn tree_counts_by_pass() {
+        let mut tree = Tree::new();
+        for k in 0..100 {
+            tree.insert(k, k >= 50);
+        }
+        let by_rem = tree.counts_by(|&k, _| k % 3);
+        check_tree(&by_rem);
+        let counts: Vec<(i32, usize)> = by_rem.iter().map(|(&b, &n)| (b, n)).collect();
+        assert_eq!(counts, vec![(0, 34), (1, 33), (2, 33)]);
+
+        let by_both = tree.counts_by(|&k, &high| (high, k % 2));
+        assert_eq!(by_both.get(&(false, 0)), Some(&25));
+        assert_eq!(by_both.get(&(true, 1)), Some(&25));
+        assert_eq!(by_both.values().sum::<usize>(), tree.len());
+        assert_eq!(tree.len(), 100);
     }
 
     #[test]
-    fn tree_test_get_pass() {
-        let mut tree_root = Tree::with(1, '1');
-        tree_root.insert(0, '0');
-        tree_root.insert(2, '2');
-        assert_eq!(tree_root.get(&0), Some(&'0'));
-        assert_eq!(tree_root.get(&1), Some(&'1'));
-        assert_eq!(tree_root.get(&2), Some(&'2'));
+    fn tree_min_max_by_value_pass() {
+        let mut scores = Tree::new();
+        for &(name, score) in &[("ann", 7), ("bob", 12), ("cat", 3), ("dan", 12), ("eve", 3)] {
+            scores.insert(name, score);
+        }
+        assert_eq!(scores.max_by_value(), Some((&"bob", &12)));
+        assert_eq!(scores.min_by_value(), Some((&"cat", &3)));
+
+        let longest = scores.max_by(|a, b| a.0.len().cmp(&b.0.len()).then(a.1.cmp(b.1)));
+        assert_eq!(longest, Some((&"bob", &12)));
+        let last = scores.max_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(last, Some((&"eve", &3)));
+        let first = scores.min_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(first, scores.first_key_value());
+
+        let empty: Tree<u8, u8> = Tree::new();
+        assert_eq!(empty.min_by_value(), None);
+        assert_eq!(empty.max_by(|a, b| a.1.cmp(b.1)), None);
     }
 
     #[test]
-    fn tree_test_iter_pass() {
-        let mut tree: Tree<u8, ()> = Tree::new();
-        for _ in 0..100 {
-            tree.insert(rand::random(), ());
+    fn tree_balance_factor_pass() {
+        let empty: Tree<u32, ()> = Tree::new();
+        assert_eq!(empty.balance_factor(), 1.0);
+        assert_eq!(Tree::with(0, ()).balance_factor(), 1.0);
+
+        let mut degenerate = Tree::new();
+        for k in 0..64u32 {
+            degenerate.insert(k, ());
         }
-        let mut iter = tree.iter();
-        let mut last = *iter.next().unwrap().0;
-        for (&k, _) in iter {
-            assert!(k > last);
-            last = k;
+        let balanced = Tree::from_sorted((0..64u32).map(|k| (k, ())).collect());
+        assert_eq!(degenerate.balance_factor(), 63.0 / 6.0);
+        assert_eq!(balanced.balance_factor(), 1.0);
+        assert!(degenerate.balance_factor() > 10.0 * balanced.balance_factor());
+    }
+
+    #[test]
+    fn tree_split_at_index_pass() {
+        for &strategy in &[
+            BalanceStrategy::None,
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            let mut tree = Tree::with_strategy(strategy);
+            for k in 0..100 {
+                tree.insert(k, k * 2);
+            }
+            let rest = tree.split_at_index(40);
+            assert_eq!(tree.len(), 40);
+            assert_eq!(rest.len(), 60);
+            assert!(tree
+                .iter()
+                .map(|(&k, &v)| (k, v))
+                .eq((0..40).map(|k| (k, k * 2))));
+            assert!(rest
+                .iter()
+                .map(|(&k, &v)| (k, v))
+                .eq((40..100).map(|k| (k, k * 2))));
+            assert_eq!(rest.strategy(), strategy);
+            check_tree(&tree);
+            check_tree(&rest);
+
+            // Every cut point of a tree shaped by scattered inserts.
+            let mut scattered = Tree::with_strategy(strategy);
+            for k in (0..150).map(|k| k * 37 % 150) {
+                scattered.insert(k, ());
+            }
+            for n in 0..=151 {
+                let mut lower = scattered.clone();
+                let upper = lower.split_at_index(n);
+                let n = n.min(150);
+                assert!(lower.keys().copied().eq(0..n));
+                assert!(upper.keys().copied().eq(n..150));
+                check_tree(&lower);
+                check_tree(&upper);
+                if strategy != BalanceStrategy::None {
+                    // Within the weakest of the bounds, about 2.4 log2(n).
+                    for half in &[&lower, &upper] {
+                        let bits = usize::BITS - half.len().leading_zeros();
+                        assert!(half.height() as u32 <= 3 * bits);
+                    }
+                }
+            }
+
+            let upper = scattered.split_off(&75);
+            assert!(upper.keys().copied().eq(75..150));
+            let upper = scattered.split_off(&200);
+            assert!(upper.is_empty());
+            assert_eq!(scattered.len(), 75);
+            check_tree(&scattered);
+        }
+
+        let mut avl = Tree::with_strategy(BalanceStrategy::Avl);
+        for k in 0..10 {
+            avl.insert(k, ());
         }
+        let upper = avl.split_off(&4);
+        assert_eq!(upper.strategy, BalanceStrategy::Avl);
+        assert_eq!(avl.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        check_tree(&avl);
+        check_tree(&upper);
+        assert!(avl.split_at_index(4).is_empty());
+        assert_eq!(avl.split_at_index(0).len(), 4);
+        assert!(avl.is_empty());
     }
 
     #[test]
-    fn node_rotate_r_pass() {
-        let mut tree = Tree::with(5, 5);
-        tree.insert(7, 7);
-        tree.insert(3, 3);
-        tree.insert(2, 2);
-        tree.insert(4, 4);
-        rotate_r(&mut tree.0);
+    #[cfg(feature = "std")]
+    fn tree_split_no_alloc_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        for k in 0..1000 {
+            tree.insert(k, ());
+        }
+        let allocs = alloc_count::get();
+        let upper = tree.split_at_index(333);
+        let top = tree.split_off(&100);
+        assert_eq!(alloc_count::get(), allocs);
+        assert_eq!((tree.len(), top.len(), upper.len()), (100, 233, 667));
     }
 
     #[test]
-    fn node_rotate_l_pass() {
-        let mut tree = Tree::with(3, 3);
-        tree.insert(2, 2);
-        tree.insert(5, 5);
-        tree.insert(4, 4);
-        tree.insert(7, 7);
-        rotate_l(&mut tree.0);
+    fn tree_verify_augmentation_pass() {
+        for &strategy in &[
+            BalanceStrategy::Avl,
+            BalanceStrategy::WeightBalanced,
+            BalanceStrategy::RedBlack,
+        ] {
+            // Sorted inserts rotate at nearly every step.
+            let mut tree = Tree::with_strategy(strategy);
+            for k in 0..64 {
+                tree.insert(k, ());
+                assert!(tree.verify_augmentation());
+            }
+            for k in (0..64).step_by(3) {
+                tree.remove(&k);
+                assert!(tree.verify_augmentation());
+            }
+        }
+
+        // A stale field on a deep node is caught as well as on the root.
+        let fresh = Tree::from_sorted((0..15).map(|k| (k, ())).collect());
+        type Corrupt = fn(&mut Node<i32, ()>);
+        let corruptions: [Corrupt; 3] = [
+            |node| node.size += 1,
+            |node| node.height += 1,
+            |node| node.black_height += 1,
+        ];
+        for corrupt in &corruptions {
+            let mut tree = fresh.clone();
+            corrupt(tree.root.as_mut().unwrap());
+            assert!(!tree.verify_augmentation());
+            assert!(!tree.validate());
+
+            let mut tree = fresh.clone();
+            corrupt(
+                tree.root
+                    .as_mut()
+                    .unwrap()
+                    .l
+                    .as_mut()
+                    .unwrap()
+                    .r
+                    .as_mut()
+                    .unwrap(),
+            );
+            assert!(!tree.verify_augmentation());
+            assert!(!tree.validate());
+        }
+
+        let mut rb = Tree::with_strategy(BalanceStrategy::RedBlack);
+        for k in 0..31 {
+            rb.insert(k, ());
+        }
+        rb.root.as_mut().unwrap().r.as_mut().unwrap().black_height -= 1;
+        assert!(!rb.verify_augmentation());
+        assert!(!rb.validate());
     }
 
     #[test]
-    fn node_rotate_roundtrip_pass() {
-        let mut tree = Tree::with(3, 3);
-        tree.insert(2, 2);
-        tree.insert(5, 5);
-        tree.insert(4, 4);
-        tree.insert(7, 7);
-        let tree_0 = tree.clone();
-        rotate_l(&mut tree.0);
-        let tree_1 = tree.clone();
-        rotate_r(&mut tree.0);
-        let tree_2 = tree;
+    #[cfg(debug_assertions)]
+    fn tree_assert_rb_invariants_pass() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        tree.assert_rb_invariants();
+        for k in 0..100 {
+            tree.insert(k, ());
+        }
+        let upper = tree.split_at_index(37);
+        tree.assert_rb_invariants();
+        upper.assert_rb_invariants();
+        let mut single = Tree::with_strategy(BalanceStrategy::RedBlack);
+        single.entry(1).or_insert(());
+        single.assert_rb_invariants();
+        single.rebalance();
+        single.assert_rb_invariants();
+    }
 
-        assert_ne!(tree_0, tree_1);
-        assert_ne!(tree_1, tree_2);
-        assert_eq!(tree_0, tree_2);
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "red root")]
+    fn tree_assert_rb_invariants_fail() {
+        let mut tree = Tree::with_strategy(BalanceStrategy::RedBlack);
+        tree.insert(1, ());
+        tree.root.as_mut().unwrap().paint(true);
+        tree.assert_rb_invariants();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "tree invariants violated")]
+    fn tree_debug_assert_valid_split_fail() {
+        // A stale size on a node the split leaves untouched.
+        let mut tree = Tree::from_sorted((0..15).map(|k| (k, ())).collect());
+        tree.root
+            .as_mut()
+            .unwrap()
+            .r
+            .as_mut()
+            .unwrap()
+            .r
+            .as_mut()
+            .unwrap()
+            .size += 1;
+        tree.split_at_index(2);
     }
 }